@@ -20,24 +20,40 @@ import dev.typester.auth2.ui.theme.Auth2Theme
 import kotlinx.coroutines.flow.MutableSharedFlow
 import kotlinx.coroutines.flow.asSharedFlow
 import kotlinx.coroutines.launch
+import uniffi.auth2.LogLevel
+import uniffi.auth2.LogRecord
 import uniffi.auth2.Logger
+import uniffi.auth2.LoggerHandle
 import uniffi.auth2.initLogger
 
 private const val TAG = "MainActivity"
 private const val LOGGER_TAG = "Core"
 
 class DebugLogger() : Logger {
-    override fun log(msg: String) {
-        Log.d(LOGGER_TAG, msg)
+    override fun log(record: LogRecord) {
+        val spans = record.spans.joinToString(":") { it.name }
+        val msg = "[${record.target}${if (spans.isEmpty()) "" else " $spans"}] ${record.message} ${record.fields}"
+        when (record.level) {
+            LogLevel.ERROR -> Log.e(LOGGER_TAG, msg)
+            LogLevel.WARN -> Log.w(LOGGER_TAG, msg)
+            LogLevel.INFO -> Log.i(LOGGER_TAG, msg)
+            LogLevel.DEBUG -> Log.d(LOGGER_TAG, msg)
+            LogLevel.TRACE -> Log.v(LOGGER_TAG, msg)
+        }
     }
 }
 
 class MainActivity : FragmentActivity() {
+    companion object {
+        // the logger is unregistered once its handle is collected
+        private var loggerHandle: LoggerHandle? = null
+    }
+
     override fun onCreate(savedInstanceState: Bundle?) {
         super.onCreate(savedInstanceState)
 
         SharedContext.setContext(applicationContext)
-        initLogger(DebugLogger())
+        loggerHandle = initLogger(DebugLogger())
 
         enableEdgeToEdge()
         setContent {
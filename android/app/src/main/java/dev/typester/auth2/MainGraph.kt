@@ -33,14 +33,9 @@ fun RootGraph() {
     var keyIsAvailable by remember { mutableStateOf(false) }
     var authenticated by remember { mutableStateOf(false) }
 
-    if (!migrationFinished) {
-        Log.d(TAG, "show migration")
-        DataMigrationScreen(
-            navigateToMain = {
-                migrationFinished = true
-            }
-        )
-    } else if (!authenticated) {
+    // the key comes first: with SQLCipher the database can't be opened, let
+    // alone migrated, without it
+    if (!authenticated) {
         Log.d(TAG, "show miometric")
         BiometricScreen(
             onFinishAuthentication = {
@@ -54,6 +49,13 @@ fun RootGraph() {
                 keyIsAvailable = true
             }
         )
+    } else if (!migrationFinished) {
+        Log.d(TAG, "show migration")
+        DataMigrationScreen(
+            navigateToMain = {
+                migrationFinished = true
+            }
+        )
     } else {
         Log.d(TAG, "show main")
         MainGraph()
@@ -16,9 +16,6 @@ class Shared private constructor() {
                 synchronized(this) {
                     if (obj == null) {
                         val databaseFile = File(SharedContext.context().filesDir, "database.db")
-                        if (!databaseFile.exists()) {
-                            databaseFile.createNewFile()
-                        }
                         val databaseUri = Uri.fromFile(databaseFile)
 
                         val config = Config(
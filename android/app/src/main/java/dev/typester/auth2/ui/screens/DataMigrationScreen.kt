@@ -45,12 +45,13 @@ fun DataMigrationScreen(
             CircularProgressIndicator()
             Text(text = "Upgrading database...")
         }
-        if (uiState.error != null) {
+        val resetToken = uiState.resetToken
+        if (uiState.error != null && resetToken != null) {
             AlertDialog(
                 onDismissRequest = {},
                 confirmButton = {
                     TextButton(onClick = {
-                        viewModel.resetAndMigrate()
+                        viewModel.resetAndMigrate(resetToken)
                     }) {
                         Text(text = "Reset database")
                     }
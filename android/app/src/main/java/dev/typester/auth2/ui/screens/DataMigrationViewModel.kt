@@ -18,6 +18,8 @@ data class DataMigrationUiState(
     val migrating: Boolean = false,
     val finished: Boolean = false,
     val error: String? = null,
+    /** Issued when the reset dialog opens, used only by its confirm button. */
+    val resetToken: String? = null,
 )
 
 class DataMigrationViewModel: ViewModel() {
@@ -38,20 +40,20 @@ class DataMigrationViewModel: ViewModel() {
                 _uiState.update { it.copy(finished = true) }
             } catch (e: Exception) {
                 Log.e(TAG, "migration error", e)
-                _uiState.update { it.copy(error = e.message) }
+                _uiState.update { it.copy(error = e.message, resetToken = core.dbResetToken()) }
             } finally {
                 _uiState.update { it.copy(migrating = false) }
             }
         }
     }
 
-    fun resetAndMigrate() {
+    fun resetAndMigrate(confirmation: String) {
         viewModelScope.launch {
-            _uiState.update { it.copy(error = null) }
+            _uiState.update { it.copy(error = null, resetToken = null) }
             try {
                 withContext(Dispatchers.IO) {
                     val core = Shared.instance()
-                    core.dbReset()
+                    core.dbReset(confirmation)
                     core.dbRunMigration()
                 }
                 _uiState.update { it.copy(finished = true) }
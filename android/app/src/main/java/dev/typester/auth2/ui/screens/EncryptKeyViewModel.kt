@@ -7,6 +7,7 @@ import android.util.Base64
 import android.util.Log
 import androidx.lifecycle.ViewModel
 import dev.typester.auth2.SharedContext
+import uniffi.auth2.KeyStoreException
 import kotlinx.coroutines.flow.MutableStateFlow
 import kotlinx.coroutines.flow.asStateFlow
 import kotlinx.coroutines.flow.update
@@ -30,7 +31,7 @@ class EncryptKeyViewModel: ViewModel() {
     init {
         try {
             initializeKeyStore()
-            getUserKey()?.let { key ->
+            getUserKey()?.toString(Charsets.UTF_8)?.let { key ->
                 updateKey(key)
                 if (key.isNotEmpty()) {
                     _uiState.update { it.copy(restored = true) }
@@ -51,7 +52,7 @@ class EncryptKeyViewModel: ViewModel() {
 
     fun saveKey() {
         try {
-            storeUserKey(uiState.value.encryptKey)
+            storeUserKey(uiState.value.encryptKey.toByteArray(Charsets.UTF_8))
             _uiState.update { it.copy(saved = true) }
         } catch (e: Exception) {
             Log.e(TAG, "failed to save key", e)
@@ -83,7 +84,7 @@ private fun initializeKeyStore() {
     }
 }
 
-private fun storeUserKey(key: String) {
+private fun storeUserKey(key: ByteArray) {
     val keyStore = KeyStore.getInstance("AndroidKeyStore")
     keyStore.load(null)
 
@@ -93,7 +94,7 @@ private fun storeUserKey(key: String) {
     val cipher = Cipher.getInstance("AES/GCM/NoPadding")
     cipher.init(Cipher.ENCRYPT_MODE, secretKey)
     val iv = cipher.iv
-    val encryptedKey = cipher.doFinal(key.toByteArray(Charsets.UTF_8))
+    val encryptedKey = cipher.doFinal(key)
 
     val base64iv = Base64.encodeToString(iv, Base64.DEFAULT)
     val base64key = Base64.encodeToString(encryptedKey, Base64.DEFAULT)
@@ -106,7 +107,7 @@ private fun storeUserKey(key: String) {
     }
 }
 
-private fun getUserKey(): String? {
+private fun getUserKey(): ByteArray? {
     val keyStore = KeyStore.getInstance("AndroidKeyStore")
     keyStore.load(null)
 
@@ -126,14 +127,39 @@ private fun getUserKey(): String? {
 
     val cipher = Cipher.getInstance("AES/GCM/NoPadding")
     cipher.init(Cipher.DECRYPT_MODE, secretKey, GCMParameterSpec(128, iv))
-    val decryptedKey = cipher.doFinal(encryptedKey)
+    return cipher.doFinal(encryptedKey)
+}
 
-    return String(decryptedKey, Charsets.UTF_8)
+private fun deleteUserKey() {
+    val sharedPreferences = SharedContext.context().getSharedPreferences(SHARED_PREFS_NAME, Context.MODE_PRIVATE)
+    with (sharedPreferences.edit()) {
+        remove(PREF_KEY_IV)
+        remove(PREF_KEY_ENCRYPTED_KEY)
+        apply()
+    }
 }
 
 class AndroidKeyStore : uniffi.auth2.KeyStore {
-    override fun get(): String? {
+    override fun get(): ByteArray? = keyStoreCall {
+        initializeKeyStore()
+        getUserKey()
+    }
+
+    override fun set(key: ByteArray) = keyStoreCall {
         initializeKeyStore()
-        return getUserKey()
+        storeUserKey(key)
+    }
+
+    override fun delete() = keyStoreCall {
+        deleteUserKey()
+    }
+
+    private fun <T> keyStoreCall(call: () -> T): T {
+        try {
+            return call()
+        } catch (e: Exception) {
+            Log.e(TAG, "key store call failed", e)
+            throw KeyStoreException.Failed(e.toString())
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,31 @@
+use std::path::Path;
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+
+use crate::protocol::{Request, Response};
+
+/// Sends one request to the agent listening on `socket`.
+pub async fn request(socket: &Path, request: &Request) -> anyhow::Result<Response> {
+    let stream = UnixStream::connect(socket).await.with_context(|| {
+        format!(
+            "no agent listening on {}, start one with `auth2-agent serve`",
+            socket.display()
+        )
+    })?;
+    let (read, mut write) = stream.into_split();
+
+    let mut json = serde_json::to_vec(request)?;
+    json.push(b'\n');
+    write.write_all(&json).await?;
+
+    let line = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await?
+        .context("agent closed the connection")?;
+    Ok(serde_json::from_str(&line)?)
+}
@@ -0,0 +1,125 @@
+use std::{fs, path::PathBuf, process::ExitCode, time::Duration};
+
+use anyhow::anyhow;
+use auth2::Error;
+use clap::{Parser, Subcommand};
+
+use common::{default_database, display_name, open, passphrase_key_store};
+use protocol::{Request, Response};
+
+mod client;
+#[allow(dead_code)]
+#[path = "../common/mod.rs"]
+mod common;
+mod protocol;
+mod server;
+
+/// Keeps the vault unlocked in the background and hands out codes over a
+/// Unix socket, so scripts don't need the passphrase.
+#[derive(Parser)]
+#[command(name = "auth2-agent", version)]
+struct Cli {
+    /// Socket path [default: <runtime dir>/auth2/agent.sock]
+    #[arg(long, env = "AUTH2_AGENT_SOCK", global = true)]
+    socket: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Ask for the passphrase and answer requests until locked
+    Serve {
+        /// Database file [default: <data dir>/auth2/auth2.db]
+        #[arg(long, env = "AUTH2_DATABASE")]
+        database: Option<PathBuf>,
+
+        /// Lock after this many seconds without a request
+        #[arg(long, default_value_t = 900)]
+        idle_timeout: u64,
+    },
+    /// List tokens
+    List,
+    /// Print the current code of a token
+    Code {
+        /// Token id, account, service, or `service:account`
+        name: String,
+    },
+    /// Make the agent forget the passphrase and exit
+    Lock,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to initialize tokio runtime");
+
+    match rt.block_on(run(cli)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("auth2-agent: {:#}", e);
+            if let Some(Error::DecryptError) = e.downcast_ref::<Error>() {
+                eprintln!("is the passphrase correct?");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let socket = match cli.socket {
+        Some(socket) => socket,
+        None => default_socket()?,
+    };
+
+    let request = match cli.command {
+        Command::Serve {
+            database,
+            idle_timeout,
+        } => {
+            let database = match database {
+                Some(database) => database,
+                None => default_database()?,
+            };
+            let auth2 = open(&database, passphrase_key_store(false)).await?;
+            // asks for the passphrase now rather than on the first request
+            auth2.unlock().await?;
+
+            let listener = server::bind(&socket)?;
+            eprintln!("listening on {}", socket.display());
+            let result = server::serve(listener, auth2, Duration::from_secs(idle_timeout)).await;
+            let _ = fs::remove_file(&socket);
+            return result;
+        }
+        Command::List => Request::List,
+        Command::Code { name } => Request::Code { name },
+        Command::Lock => Request::Lock,
+    };
+
+    match client::request(&socket, &request).await? {
+        Response::Tokens { tokens } => {
+            for token in tokens {
+                println!(
+                    "{:<20} {}",
+                    token.id,
+                    display_name(&token.account, token.service.as_deref())
+                );
+            }
+        }
+        Response::Code { code, .. } => println!("{}", code),
+        Response::Locked => eprintln!("agent locked"),
+        Response::Error { message } => return Err(anyhow!(message)),
+    }
+    Ok(())
+}
+
+fn default_socket() -> anyhow::Result<PathBuf> {
+    let dir = dirs::runtime_dir()
+        .or_else(dirs::data_local_dir)
+        .ok_or_else(|| anyhow!("no runtime directory, pass --socket"))?;
+    Ok(dir.join("auth2").join("agent.sock"))
+}
@@ -0,0 +1,32 @@
+//! One JSON object per line in each direction: the client writes a
+//! `Request`, the agent answers with a `Response`.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    List,
+    /// `name` is a token id, account, service or `service:account`.
+    Code {
+        name: String,
+    },
+    /// Forget the passphrase and shut the agent down.
+    Lock,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Tokens { tokens: Vec<TokenInfo> },
+    Code { code: String, expires: u32 },
+    Locked,
+    Error { message: String },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub id: u64,
+    pub account: String,
+    pub service: Option<String>,
+}
@@ -0,0 +1,312 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use auth2::Auth2;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+    time::{sleep, Instant},
+};
+
+use crate::{
+    common::tokens::find_token,
+    protocol::{Request, Response, TokenInfo},
+};
+
+enum Event {
+    Activity,
+    Lock,
+}
+
+/// Binds `socket` inside a directory only the current user can enter,
+/// replacing a socket left behind by an agent that didn't shut down cleanly.
+/// A missing directory is created; an existing one is left as it is, so it
+/// must already be private.
+pub fn bind(socket: &Path) -> anyhow::Result<UnixListener> {
+    if let Some(dir) = socket.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        match fs::metadata(dir) {
+            Ok(metadata) => {
+                // SAFETY: geteuid can't fail
+                if metadata.uid() != unsafe { libc::geteuid() } {
+                    bail!("{} is owned by another user", dir.display());
+                }
+                if metadata.mode() & 0o077 != 0 {
+                    bail!(
+                        "{} is accessible by other users, pass a --socket in a private directory",
+                        dir.display()
+                    );
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    match fs::symlink_metadata(socket) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            bail!("{} exists and is not a socket", socket.display());
+        }
+        Ok(_) => {
+            if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+                bail!("an agent is already listening on {}", socket.display());
+            }
+            fs::remove_file(socket)?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answers requests with the unlocked `auth2` until it is told to lock,
+/// sees no request for `idle_timeout`, or gets SIGINT/SIGTERM. Connections
+/// from other users are rejected.
+pub async fn serve(
+    listener: UnixListener,
+    auth2: Arc<Auth2>,
+    idle_timeout: Duration,
+) -> anyhow::Result<()> {
+    let socket = listener.local_addr()?;
+    let socket = socket.as_pathname().context("socket has no path")?;
+    let owner = fs::metadata(socket)?.uid();
+
+    let (tx, mut rx) = mpsc::channel(16);
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let idle = sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                match stream.peer_cred() {
+                    Ok(cred) if cred.uid() == owner => (),
+                    _ => {
+                        eprintln!("rejected a connection from another user");
+                        continue;
+                    }
+                }
+                idle.as_mut().reset(Instant::now() + idle_timeout);
+                tokio::spawn(handle(stream, auth2.clone(), tx.clone()));
+            }
+            Some(event) = rx.recv() => match event {
+                Event::Activity => idle.as_mut().reset(Instant::now() + idle_timeout),
+                Event::Lock => break,
+            },
+            _ = &mut idle => {
+                eprintln!("idle for {}s, locking", idle_timeout.as_secs());
+                break;
+            }
+            _ = interrupt.recv() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+
+    auth2.lock().await;
+    Ok(())
+}
+
+async fn handle(stream: UnixStream, auth2: Arc<Auth2>, events: mpsc::Sender<Event>) {
+    if let Err(e) = handle_requests(stream, &auth2, &events).await {
+        eprintln!("connection error: {:#}", e);
+    }
+}
+
+async fn handle_requests(
+    stream: UnixStream,
+    auth2: &Auth2,
+    events: &mpsc::Sender<Event>,
+) -> anyhow::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let _ = events.send(Event::Activity).await;
+
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => respond(auth2, request).await,
+            Err(e) => Response::Error {
+                message: format!("invalid request: {}", e),
+            },
+        };
+
+        let mut json = serde_json::to_vec(&response)?;
+        json.push(b'\n');
+        write.write_all(&json).await?;
+
+        if response == Response::Locked {
+            let _ = events.send(Event::Lock).await;
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn respond(auth2: &Auth2, request: Request) -> Response {
+    let result = match request {
+        Request::List => list(auth2).await,
+        Request::Code { name } => code(auth2, &name).await,
+        Request::Lock => Ok(Response::Locked),
+    };
+    result.unwrap_or_else(|e| Response::Error {
+        message: format!("{:#}", e),
+    })
+}
+
+async fn list(auth2: &Auth2) -> anyhow::Result<Response> {
+    let tokens = auth2
+        .list_tokens(None)
+        .await?
+        .into_iter()
+        .map(|token| TokenInfo {
+            id: token.id,
+            account: token.account,
+            service: token.service,
+        })
+        .collect();
+    Ok(Response::Tokens { tokens })
+}
+
+async fn code(auth2: &Auth2, name: &str) -> anyhow::Result<Response> {
+    let token = find_token(auth2, name).await?;
+    let result = auth2.generate_current(token.id).await?;
+    Ok(Response::Code {
+        code: result.current,
+        expires: result.expires,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::fs::PermissionsExt,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use auth2::{Auth2, Config, MemoryKeyStore};
+    use tempfile::tempdir;
+
+    use super::{bind, serve};
+    use crate::{
+        client::request,
+        protocol::{Request, Response},
+    };
+
+    async fn auth2() -> Arc<Auth2> {
+        let auth2 = Auth2::new(Config::new("sqlite::memory:", MemoryKeyStore::new("test")))
+            .await
+            .unwrap();
+        auth2.db_run_migration().await.unwrap();
+        auth2
+            .add_token(
+                "dameleon".into(),
+                Some("GitHub".into()),
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        auth2
+    }
+
+    #[tokio::test]
+    async fn test_agent() {
+        let temp_dir = tempdir().unwrap();
+        let socket = temp_dir.path().join("agent").join("agent.sock");
+
+        let listener = bind(&socket).unwrap();
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&socket), 0o600);
+        assert_eq!(mode(socket.parent().unwrap()), 0o700);
+        assert!(bind(&socket).is_err());
+
+        let server = tokio::spawn(serve(listener, auth2().await, Duration::from_secs(60)));
+
+        let Response::Tokens { tokens } = request(&socket, &Request::List).await.unwrap() else {
+            panic!("unexpected response");
+        };
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].account, "dameleon");
+
+        let response = request(
+            &socket,
+            &Request::Code {
+                name: "github".into(),
+            },
+        )
+        .await
+        .unwrap();
+        let Response::Code { code, .. } = response else {
+            panic!("unexpected response: {:?}", response);
+        };
+        assert_eq!(code.len(), 6);
+
+        let response = request(
+            &socket,
+            &Request::Code {
+                name: "gitlab".into(),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(matches!(response, Response::Error { .. }));
+
+        assert_eq!(
+            request(&socket, &Request::Lock).await.unwrap(),
+            Response::Locked
+        );
+        server.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_bind_refuses_unsafe_paths() {
+        let temp_dir = tempdir().unwrap();
+
+        let shared = temp_dir.path().join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(bind(&shared.join("agent.sock")).is_err());
+        // left alone rather than tightened
+        assert_eq!(
+            std::fs::metadata(&shared).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+
+        let file = temp_dir.path().join("agent.sock");
+        std::fs::write(&file, "keep").unwrap();
+        assert!(bind(&file).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep");
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let temp_dir = tempdir().unwrap();
+        let socket = temp_dir.path().join("agent").join("agent.sock");
+
+        let listener = bind(&socket).unwrap();
+        let started = Instant::now();
+        serve(listener, auth2().await, Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
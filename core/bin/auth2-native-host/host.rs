@@ -0,0 +1,223 @@
+use auth2::{Auth2, Token};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    common::tokens::match_tokens,
+    protocol::{read_message, write_message, DomainCode, Request, Response, TokenInfo},
+};
+
+/// Answers requests read from `reader` until the browser closes it.
+pub async fn serve<R, W>(auth2: &Auth2, mut reader: R, mut writer: W) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    while let Some(request) = read_message(&mut reader).await? {
+        let response = match request {
+            Ok(request) => respond(auth2, request).await,
+            Err(e) => Response::Error {
+                message: format!("invalid request: {}", e),
+            },
+        };
+        write_message(&mut writer, &response).await?;
+    }
+    Ok(())
+}
+
+async fn respond(auth2: &Auth2, request: Request) -> Response {
+    let result = match request {
+        Request::List => list(auth2, None).await,
+        Request::Search { query } => list(auth2, Some(&query)).await,
+        Request::CodeForDomain { domain } => code_for_domain(auth2, &domain).await,
+    };
+    result.unwrap_or_else(|e| Response::Error {
+        message: format!("{:#}", e),
+    })
+}
+
+async fn list(auth2: &Auth2, query: Option<&str>) -> anyhow::Result<Response> {
+    let mut tokens = auth2.list_tokens(None).await?;
+    if let Some(query) = query {
+        tokens = match_tokens(tokens, query);
+    }
+    let tokens = tokens
+        .into_iter()
+        .map(|token| TokenInfo {
+            id: token.id,
+            account: token.account,
+            service: token.service,
+        })
+        .collect();
+    Ok(Response::Tokens { tokens })
+}
+
+async fn code_for_domain(auth2: &Auth2, domain: &str) -> anyhow::Result<Response> {
+    let tokens: Vec<Token> = auth2
+        .list_tokens(None)
+        .await?
+        .into_iter()
+        .filter(|t| {
+            t.service
+                .as_deref()
+                .is_some_and(|s| matches_domain(s, domain))
+        })
+        .collect();
+
+    let mut codes = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let result = auth2.generate_current(token.id).await?;
+        codes.push(DomainCode {
+            id: token.id,
+            account: token.account,
+            service: token.service,
+            code: result.current,
+            expires: result.expires,
+        });
+    }
+    Ok(Response::Codes { codes })
+}
+
+/// Whether a token issued by `service` belongs on a page of `domain`. A
+/// service that looks like a domain must be `domain` or a parent of it.
+/// Otherwise, ignoring case and anything but letters and digits, `domain`
+/// must be the service followed by a public suffix, optionally behind
+/// `www.`: `GitHub` matches `github.com` and `www.github.co.uk`, but not
+/// `gist.github.com` or `attacker.github.io`. Subdomains need the service
+/// spelled out as a domain.
+fn matches_domain(service: &str, domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    let service = service.trim().to_lowercase();
+
+    if service.contains('.') {
+        let service = service.trim_end_matches('.');
+        return domain == service || domain.ends_with(&format!(".{}", service));
+    }
+
+    let service: String = service.chars().filter(|c| c.is_alphanumeric()).collect();
+    if service.is_empty() {
+        return false;
+    }
+    let domain = domain.strip_prefix("www.").unwrap_or(&domain);
+    domain
+        .strip_prefix(&service)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .is_some_and(is_public_suffix)
+}
+
+/// Second-level labels that are part of the public suffix under a country
+/// TLD, as in `co.uk` or `com.au`.
+const SECOND_LEVEL_SUFFIXES: &[&str] = &["ac", "co", "com", "edu", "gov", "ne", "net", "or", "org"];
+
+/// Whether `suffix` is a TLD, or one of the common second-level suffixes
+/// of a country TLD. Anything longer, such as `github.io`, is a host on
+/// which others can register names.
+fn is_public_suffix(suffix: &str) -> bool {
+    match suffix.split_once('.') {
+        None => !suffix.is_empty(),
+        Some((label, tld)) => tld.len() == 2 && SECOND_LEVEL_SUFFIXES.contains(&label),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use auth2::{Auth2, Config, MemoryKeyStore};
+
+    use super::{matches_domain, serve};
+    use crate::protocol::{read_message, write_message, Request, Response};
+
+    async fn next(output: &mut &[u8]) -> Response {
+        read_message(output).await.unwrap().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_matches_domain() {
+        assert!(matches_domain("GitHub", "github.com"));
+        assert!(matches_domain("GitHub", "www.github.com"));
+        assert!(matches_domain("Google", "google.co.uk"));
+        assert!(matches_domain("google.com", "accounts.google.com"));
+        assert!(matches_domain("aws.amazon.com", "signin.aws.amazon.com"));
+        assert!(matches_domain("example.org", "login.example.org"));
+        assert!(matches_domain("example.org", "example.org."));
+        assert!(!matches_domain("example.org", "example.org.evil.com"));
+        assert!(!matches_domain("GitHub", "gitlab.com"));
+        assert!(!matches_domain("com", "github.com"));
+        assert!(!matches_domain("Hub", "github.com"));
+        assert!(!matches_domain("!!", "github.com"));
+        assert!(!matches_domain("GitHub", "github.evil.com"));
+        assert!(!matches_domain("GitHub", "github.com.evil.io"));
+        assert!(!matches_domain("GitHub", "github.attacker.net"));
+        assert!(!matches_domain("AWS", "signin.aws.amazon.com"));
+        assert!(!matches_domain("co", "example.co.uk"));
+        assert!(!matches_domain("GitHub", "attacker.github.io"));
+        assert!(!matches_domain("GitLab", "attacker.gitlab.io"));
+        assert!(!matches_domain("GitHub", "github.io.evil.com"));
+        assert!(!matches_domain("Google", "accounts.google.com"));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let auth2 = Auth2::new(Config::new("sqlite::memory:", MemoryKeyStore::new("test")))
+            .await
+            .unwrap();
+        auth2.db_run_migration().await.unwrap();
+        for (account, service) in [("dameleon", "GitHub"), ("typester", "GitLab")] {
+            auth2
+                .add_token(
+                    account.into(),
+                    Some(service.into()),
+                    "JBSWY3DPEHPK3PXP".into(),
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+
+        let mut input = vec![];
+        write_message(&mut input, &Request::List).await.unwrap();
+        write_message(
+            &mut input,
+            &Request::Search {
+                query: "lab".into(),
+            },
+        )
+        .await
+        .unwrap();
+        write_message(
+            &mut input,
+            &Request::CodeForDomain {
+                domain: "github.com".into(),
+            },
+        )
+        .await
+        .unwrap();
+        input.extend_from_slice(&2u32.to_ne_bytes());
+        input.extend_from_slice(b"{}");
+
+        let mut output = vec![];
+        serve(&auth2, &input[..], &mut output).await.unwrap();
+
+        let mut output = &output[..];
+
+        let Response::Tokens { tokens } = next(&mut output).await else {
+            panic!("unexpected response");
+        };
+        assert_eq!(tokens.len(), 2);
+
+        let Response::Tokens { tokens } = next(&mut output).await else {
+            panic!("unexpected response");
+        };
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].account, "typester");
+
+        let Response::Codes { codes } = next(&mut output).await else {
+            panic!("unexpected response");
+        };
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].account, "dameleon");
+        assert_eq!(codes[0].code.len(), 6);
+
+        assert!(matches!(next(&mut output).await, Response::Error { .. }));
+    }
+}
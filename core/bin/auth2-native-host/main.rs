@@ -0,0 +1,150 @@
+//! Native messaging host for the browser extension. The browser starts it
+//! with the calling extension's origin as an argument and talks to it over
+//! stdin/stdout, see `protocol`. Only extensions listed in
+//! `AUTH2_ALLOWED_ORIGINS` (comma separated) or, failing that, in the
+//! `allowed-origins` file of the auth2 config directory (one per line) are
+//! served.
+
+use std::{env, fs, io::ErrorKind, path::PathBuf, process::ExitCode, sync::Arc};
+
+use anyhow::{anyhow, bail, Context};
+
+use auth2::EnvKeyStore;
+
+use common::{default_database, open};
+use protocol::{write_message, Response};
+
+#[allow(dead_code)]
+#[path = "../common/mod.rs"]
+mod common;
+mod host;
+mod protocol;
+
+const ALLOWED_ORIGINS_ENV: &str = "AUTH2_ALLOWED_ORIGINS";
+const ALLOWED_ORIGINS_FILE: &str = "allowed-origins";
+
+fn main() -> ExitCode {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to initialize tokio runtime");
+
+    match rt.block_on(run()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // stderr ends up in the browser's log
+            eprintln!("auth2-native-host: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut stdout = tokio::io::stdout();
+
+    let origin = caller_origin(&args);
+    let allowed = allowed_origins()?;
+    if !origin.is_some_and(|origin| is_allowed(&allowed, origin)) {
+        let message = format!("origin {} is not allowed", origin.unwrap_or("(none)"));
+        write_message(
+            &mut stdout,
+            &Response::Error {
+                message: message.clone(),
+            },
+        )
+        .await?;
+        bail!(message);
+    }
+
+    let database = match env::var_os("AUTH2_DATABASE") {
+        Some(database) => PathBuf::from(database),
+        None => default_database()?,
+    };
+    // stdin belongs to the browser, so the passphrase can only come from
+    // the environment
+    let auth2 = open(&database, Arc::new(EnvKeyStore::default())).await?;
+
+    host::serve(&auth2, tokio::io::stdin(), stdout).await
+}
+
+/// Chrome passes `chrome-extension://<id>/` (followed by `--parent-window`
+/// on Windows), Firefox the path of the host manifest and the extension id.
+fn caller_origin(args: &[String]) -> Option<&str> {
+    if let Some(origin) = args.iter().find(|a| a.starts_with("chrome-extension://")) {
+        return Some(origin);
+    }
+    match args {
+        [_manifest, extension_id] => Some(extension_id),
+        _ => None,
+    }
+}
+
+fn is_allowed(allowed: &[String], origin: &str) -> bool {
+    let origin = origin.trim_end_matches('/');
+    allowed.iter().any(|a| a.trim_end_matches('/') == origin)
+}
+
+fn allowed_origins() -> anyhow::Result<Vec<String>> {
+    if let Ok(origins) = env::var(ALLOWED_ORIGINS_ENV) {
+        return Ok(parse_origins(&origins, ','));
+    }
+
+    let dir = dirs::config_dir().ok_or_else(|| anyhow!("no config directory"))?;
+    let path = dir.join("auth2").join(ALLOWED_ORIGINS_FILE);
+    match fs::read_to_string(&path) {
+        Ok(origins) => Ok(parse_origins(&origins, '\n')),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Splits on `separator`, skipping blank entries and `#` comments.
+fn parse_origins(origins: &str, separator: char) -> Vec<String> {
+    origins
+        .split(separator)
+        .map(str::trim)
+        .filter(|o| !o.is_empty() && !o.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{caller_origin, is_allowed, parse_origins};
+
+    #[test]
+    fn test_origin() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            caller_origin(&args(&["chrome-extension://abc/"])),
+            Some("chrome-extension://abc/")
+        );
+        assert_eq!(
+            caller_origin(&args(&["chrome-extension://abc/", "--parent-window=0"])),
+            Some("chrome-extension://abc/")
+        );
+        assert_eq!(
+            caller_origin(&args(&["/path/auth2.json", "auth2@example.org"])),
+            Some("auth2@example.org")
+        );
+        assert_eq!(caller_origin(&args(&[])), None);
+
+        let allowed = parse_origins(
+            "# chrome\nchrome-extension://abc/\n\nauth2@example.org\n",
+            '\n',
+        );
+        assert_eq!(allowed, ["chrome-extension://abc/", "auth2@example.org"]);
+        assert!(is_allowed(&allowed, "chrome-extension://abc/"));
+        assert!(is_allowed(&allowed, "chrome-extension://abc"));
+        assert!(is_allowed(&allowed, "auth2@example.org"));
+        assert!(!is_allowed(&allowed, "chrome-extension://xyz/"));
+        assert!(!is_allowed(&[], "chrome-extension://abc/"));
+
+        assert_eq!(
+            parse_origins(" chrome-extension://abc/ , auth2@example.org,", ','),
+            ["chrome-extension://abc/", "auth2@example.org"]
+        );
+    }
+}
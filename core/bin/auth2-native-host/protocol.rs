@@ -0,0 +1,140 @@
+//! The browser's native messaging framing: each message is a UTF-8 JSON
+//! object preceded by its length as a 32-bit unsigned integer in native
+//! byte order. The extension sends a `Request`, the host answers with a
+//! `Response`.
+
+use std::io::ErrorKind;
+
+use anyhow::bail;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Browsers refuse messages from the host larger than this, and nothing the
+/// extension sends needs to be bigger either.
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    List,
+    /// Tokens whose id, account, service or `service:account` matches
+    /// `query`.
+    Search {
+        query: String,
+    },
+    /// Current codes of the tokens whose service is `domain` or a part of
+    /// it, e.g. `GitHub` for `github.com`.
+    CodeForDomain {
+        domain: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Tokens { tokens: Vec<TokenInfo> },
+    Codes { codes: Vec<DomainCode> },
+    Error { message: String },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub id: u64,
+    pub account: String,
+    pub service: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DomainCode {
+    pub id: u64,
+    pub account: String,
+    pub service: Option<String>,
+    pub code: String,
+    pub expires: u32,
+}
+
+/// Reads the next message, or `None` once the browser closes the pipe.
+pub async fn read_message<R, T>(reader: &mut R) -> anyhow::Result<Option<serde_json::Result<T>>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_ne_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        bail!("message of {} bytes is too large", len);
+    }
+    let mut json = vec![0; len];
+    reader.read_exact(&mut json).await?;
+    Ok(Some(serde_json::from_slice(&json)))
+}
+
+pub async fn write_message<W, T>(writer: &mut W, message: &T) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let json = serde_json::to_vec(message)?;
+    if json.len() > MAX_MESSAGE_SIZE {
+        bail!("message of {} bytes is too large", json.len());
+    }
+    writer.write_all(&(json.len() as u32).to_ne_bytes()).await?;
+    writer.write_all(&json).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, write_message, Request, MAX_MESSAGE_SIZE};
+
+    #[tokio::test]
+    async fn test_framing() {
+        let mut buf = vec![];
+        write_message(&mut buf, &Request::List).await.unwrap();
+        write_message(
+            &mut buf,
+            &Request::CodeForDomain {
+                domain: "github.com".into(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let json = br#"{"type":"list"}"#;
+        assert_eq!(&buf[..4], &(json.len() as u32).to_ne_bytes());
+        assert_eq!(&buf[4..4 + json.len()], json);
+
+        let mut reader = &buf[..];
+        let first: Request = read_message(&mut reader).await.unwrap().unwrap().unwrap();
+        assert_eq!(first, Request::List);
+        let second: Request = read_message(&mut reader).await.unwrap().unwrap().unwrap();
+        assert_eq!(
+            second,
+            Request::CodeForDomain {
+                domain: "github.com".into()
+            }
+        );
+        assert!(read_message::<_, Request>(&mut reader)
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut invalid = vec![];
+        invalid.extend_from_slice(&5u32.to_ne_bytes());
+        invalid.extend_from_slice(b"hello");
+        let message = read_message::<_, Request>(&mut &invalid[..]).await.unwrap();
+        assert!(message.unwrap().is_err());
+
+        let too_large = ((MAX_MESSAGE_SIZE + 1) as u32).to_ne_bytes();
+        assert!(read_message::<_, Request>(&mut &too_large[..])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    io,
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use auth2::{Auth2, Error};
+use clap::Parser;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+
+use common::{default_database, display_name, open, passphrase_key_store};
+
+// shared with the other binaries, not every helper is used here
+#[allow(dead_code)]
+#[path = "../common/mod.rs"]
+mod common;
+
+/// How often the screen is redrawn when no key is pressed.
+const TICK: Duration = Duration::from_millis(250);
+const BAR_WIDTH: usize = 10;
+/// Remaining seconds from which a code is shown as about to expire.
+const EXPIRING_SECS: u64 = 5;
+
+/// Live-updating codes of every token in the terminal.
+#[derive(Parser)]
+#[command(name = "auth2-tui", version)]
+struct Cli {
+    /// Database file [default: <data dir>/auth2/auth2.db]
+    #[arg(long, env = "AUTH2_DATABASE")]
+    database: Option<PathBuf>,
+}
+
+struct Entry {
+    id: u64,
+    name: String,
+    period: u32,
+    code: Option<Code>,
+}
+
+struct Code {
+    current: String,
+    expires_at: Instant,
+}
+
+struct App {
+    entries: Vec<Entry>,
+    filter: String,
+    /// Position in `visible()`.
+    selected: usize,
+    matcher: SkimMatcherV2,
+}
+
+enum Action {
+    Continue,
+    Quit,
+    Select(u64),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to initialize tokio runtime");
+
+    match rt.block_on(run(cli)) {
+        Ok(Some(code)) => {
+            println!("{}", code);
+            ExitCode::SUCCESS
+        }
+        Ok(None) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("auth2-tui: {:#}", e);
+            if let Some(Error::DecryptError) = e.downcast_ref::<Error>() {
+                eprintln!("is the passphrase correct?");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns the code picked with enter, if any.
+async fn run(cli: Cli) -> anyhow::Result<Option<String>> {
+    let database = match cli.database {
+        Some(database) => database,
+        None => default_database()?,
+    };
+    // asks for the passphrase before the terminal switches to raw mode
+    let auth2 = open(&database, passphrase_key_store(false)).await?;
+
+    let mut entries = vec![];
+    for token in auth2.list_tokens(None).await? {
+        let period = match auth2.token_detail(token.id).await? {
+            Some(detail) => detail.period,
+            None => continue,
+        };
+        entries.push(Entry {
+            id: token.id,
+            name: display_name(&token.account, token.service.as_deref()),
+            period,
+            code: None,
+        });
+    }
+    let mut app = App::new(entries);
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, &auth2).await;
+    ratatui::restore();
+
+    match result? {
+        Some(id) => Ok(Some(auth2.generate_current(id).await?.current)),
+        None => Ok(None),
+    }
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    auth2: &Auth2,
+) -> anyhow::Result<Option<u64>> {
+    loop {
+        app.refresh_codes(auth2).await?;
+        terminal.draw(|frame| app.draw(frame))?;
+
+        let event = tokio::task::spawn_blocking(|| -> io::Result<Option<Event>> {
+            if event::poll(TICK)? {
+                Ok(Some(event::read()?))
+            } else {
+                Ok(None)
+            }
+        })
+        .await??;
+
+        if let Some(Event::Key(key)) = event {
+            match app.handle_key(key) {
+                Action::Continue => (),
+                Action::Quit => return Ok(None),
+                Action::Select(id) => return Ok(Some(id)),
+            }
+        }
+    }
+}
+
+impl App {
+    fn new(entries: Vec<Entry>) -> Self {
+        Self {
+            entries,
+            filter: String::new(),
+            selected: 0,
+            matcher: SkimMatcherV2::default(),
+        }
+    }
+
+    /// Indices of the entries matching the filter, best match first.
+    fn visible(&self) -> Vec<usize> {
+        if self.filter.is_empty() {
+            return (0..self.entries.len()).collect();
+        }
+
+        let mut scored: Vec<_> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                self.matcher
+                    .fuzzy_match(&entry.name, &self.filter)
+                    .map(|score| (i, score))
+            })
+            .collect();
+        scored.sort_by_key(|&(i, score)| (std::cmp::Reverse(score), i));
+        scored.into_iter().map(|(i, _)| i).collect()
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let len = self.visible().len();
+        if len == 0 {
+            self.selected = 0;
+            return;
+        }
+        self.selected = self.selected.saturating_add_signed(delta).min(len - 1);
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::Continue;
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return Action::Quit,
+            KeyCode::Esc if self.filter.is_empty() => return Action::Quit,
+            KeyCode::Esc => self.filter.clear(),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Enter => {
+                if let Some(&i) = self.visible().get(self.selected) {
+                    return Action::Select(self.entries[i].id);
+                }
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.filter.push(c);
+                self.selected = 0;
+            }
+            _ => (),
+        }
+        Action::Continue
+    }
+
+    /// Fetches codes of visible entries that are missing or expired.
+    async fn refresh_codes(&mut self, auth2: &Auth2) -> anyhow::Result<()> {
+        let now = Instant::now();
+        for i in self.visible() {
+            let entry = &mut self.entries[i];
+            if entry
+                .code
+                .as_ref()
+                .is_some_and(|code| code.expires_at > now)
+            {
+                continue;
+            }
+            let result = auth2.current_code(entry.id).await?;
+            entry.code = Some(Code {
+                current: result.current,
+                expires_at: now + Duration::from_secs(result.expires as u64),
+            });
+        }
+        Ok(())
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [filter_area, table_area, help_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(Line::from(format!("> {}", self.filter)), filter_area);
+        frame.set_cursor_position((
+            filter_area.x + 2 + self.filter.chars().count() as u16,
+            filter_area.y,
+        ));
+
+        let now = Instant::now();
+        let rows = self.visible().into_iter().map(|i| {
+            let entry = &self.entries[i];
+            let Some(code) = &entry.code else {
+                return Row::new(vec![entry.name.clone()]);
+            };
+            let remaining = code.expires_at.saturating_duration_since(now);
+            let style = if remaining.as_secs() < EXPIRING_SECS {
+                Style::new().fg(Color::Red)
+            } else {
+                Style::new().fg(Color::Green)
+            };
+            Row::new(vec![
+                Line::from(entry.name.clone()),
+                Line::from(group_digits(&code.current)).style(style.bold()),
+                Line::from(format!(
+                    "{} {:>2}s",
+                    countdown_bar(remaining, entry.period, BAR_WIDTH),
+                    remaining.as_secs()
+                ))
+                .style(style),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(20),
+                Constraint::Length(10),
+                Constraint::Length(BAR_WIDTH as u16 + 4),
+            ],
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(table, table_area, &mut state);
+
+        frame.render_widget(
+            Line::from("type to filter · ↑/↓ select · enter print code · esc quit").dim(),
+            help_area,
+        );
+    }
+}
+
+/// Splits a code in two halves for readability, e.g. `123 456`.
+fn group_digits(code: &str) -> String {
+    let (head, tail) = code.split_at(code.len() / 2);
+    format!("{} {}", head, tail)
+}
+
+/// A bar of `width` cells, filled in proportion to the time left of `period`.
+fn countdown_bar(remaining: Duration, period: u32, width: usize) -> String {
+    let period = Duration::from_secs(period.max(1) as u64);
+    let ratio = remaining.min(period).as_secs_f64() / period.as_secs_f64();
+    let filled = (ratio * width as f64).ceil() as usize;
+    format!("{}{}", "█".repeat(filled), "░".repeat(width - filled))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{countdown_bar, group_digits, Action, App, Entry};
+
+    fn app() -> App {
+        App::new(
+            ["GitHub:dameleon", "GitLab:dameleon", "Google:typester"]
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| Entry {
+                    id: i as u64 + 1,
+                    name: name.into(),
+                    period: 30,
+                    code: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Action {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut app = app();
+        assert_eq!(app.visible(), [0, 1, 2]);
+
+        press(&mut app, KeyCode::Char('g'));
+        press(&mut app, KeyCode::Char('t'));
+        assert_eq!(app.visible().len(), 3);
+        press(&mut app, KeyCode::Char('l'));
+        // "GitLab:dameleon" matches better than "GitHub:dameleon"
+        assert_eq!(app.visible(), [1, 0]);
+
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Char('y'));
+        assert_eq!(app.visible(), [2]);
+
+        assert!(matches!(press(&mut app, KeyCode::Enter), Action::Select(3)));
+        assert!(matches!(press(&mut app, KeyCode::Esc), Action::Continue));
+        assert_eq!(app.filter, "");
+        assert!(matches!(press(&mut app, KeyCode::Esc), Action::Quit));
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut app = app();
+        press(&mut app, KeyCode::Up);
+        assert_eq!(app.selected, 0);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.selected, 2);
+        assert!(matches!(press(&mut app, KeyCode::Enter), Action::Select(3)));
+
+        let ctrl_p = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL);
+        app.handle_key(ctrl_p);
+        assert_eq!(app.selected, 1);
+        assert_eq!(app.filter, "");
+    }
+
+    #[test]
+    fn test_countdown_bar() {
+        assert_eq!(countdown_bar(Duration::from_secs(30), 30, 10), "██████████");
+        assert_eq!(countdown_bar(Duration::from_secs(15), 30, 10), "█████░░░░░");
+        assert_eq!(countdown_bar(Duration::ZERO, 30, 10), "░░░░░░░░░░");
+        assert_eq!(countdown_bar(Duration::from_secs(90), 30, 4), "████");
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!(group_digits("123456"), "123 456");
+        assert_eq!(group_digits("12345678"), "1234 5678");
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{bail, Context};
+use auth2::{read_secret, Auth2, Config, Error, TokenAlg};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use common::{
+    database_url, default_database, display_name, open, passphrase_key_store, tokens::find_token,
+};
+
+#[allow(dead_code)]
+#[path = "../common/mod.rs"]
+mod common;
+
+/// Two-factor authentication codes in the terminal.
+#[derive(Parser)]
+#[command(name = "auth2", version)]
+struct Cli {
+    /// Database file [default: <data dir>/auth2/auth2.db]
+    #[arg(long, env = "AUTH2_DATABASE", global = true)]
+    database: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new database protected by a passphrase
+    Init,
+    /// Add a token; the secret is read from the terminal or stdin
+    Add(AddArgs),
+    /// List tokens
+    List,
+    /// Print the current code of a token
+    Code {
+        /// Token id, account, service, or `service:account`
+        name: String,
+    },
+    /// Remove a token
+    Rm {
+        /// Token id, account, service, or `service:account`
+        name: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Import otpauth:// URLs, one per line
+    Import {
+        /// File to read, `-` for stdin
+        file: PathBuf,
+    },
+    /// Export all tokens as otpauth:// URLs, one per line. The output
+    /// contains the secrets.
+    Export {
+        /// File to write [default: stdout]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct AddArgs {
+    /// Read an otpauth:// URL instead of a secret
+    #[arg(long, conflicts_with_all = ["account", "service", "algorithm", "digits", "period"])]
+    url: bool,
+
+    /// Account name
+    #[arg(required_unless_present = "url")]
+    account: Option<String>,
+
+    #[arg(long)]
+    service: Option<String>,
+
+    #[arg(long, value_enum)]
+    algorithm: Option<Algorithm>,
+
+    #[arg(long)]
+    digits: Option<u8>,
+
+    /// Seconds each code is valid for
+    #[arg(long)]
+    period: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl From<Algorithm> for TokenAlg {
+    fn from(v: Algorithm) -> Self {
+        match v {
+            Algorithm::Sha1 => Self::Sha1,
+            Algorithm::Sha256 => Self::Sha256,
+            Algorithm::Sha512 => Self::Sha512,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to initialize tokio runtime");
+
+    match rt.block_on(run(cli)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("auth2: {:#}", e);
+            if let Some(Error::DecryptError) = e.downcast_ref::<Error>() {
+                eprintln!("is the passphrase correct?");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let database = match cli.database {
+        Some(database) => database,
+        None => default_database()?,
+    };
+
+    if let Command::Init = cli.command {
+        return init(&database).await;
+    }
+    let auth2 = open(&database, passphrase_key_store(false)).await?;
+
+    match cli.command {
+        Command::Init => unreachable!(),
+        Command::Add(args) => add(&auth2, args).await,
+        Command::List => list(&auth2).await,
+        Command::Code { name } => code(&auth2, &name).await,
+        Command::Rm { name, yes } => rm(&auth2, &name, yes).await,
+        Command::Import { file } => import(&auth2, &file).await,
+        Command::Export { file } => export(&auth2, file.as_deref()).await,
+    }
+}
+
+async fn init(database: &Path) -> anyhow::Result<()> {
+    if database.exists() {
+        bail!("{} already exists", database.display());
+    }
+    if let Some(dir) = database.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let key_store = passphrase_key_store(true);
+    // fail before creating anything if no passphrase was given
+    if key_store.get()?.is_none() {
+        bail!("no passphrase given");
+    }
+
+    let auth2 = Auth2::new(Config {
+        database_url: database_url(database)?,
+        key_store,
+    })
+    .await?;
+    auth2.db_run_migration().await?;
+    // wraps the data key with the passphrase that was just confirmed, rather
+    // than whatever is typed on first use
+    auth2.unlock().await?;
+
+    eprintln!("created {}", database.display());
+    Ok(())
+}
+
+async fn add(auth2: &Auth2, args: AddArgs) -> anyhow::Result<()> {
+    let token = if args.url {
+        let url = read_secret("otpauth URL: ")?;
+        auth2.add_token_from_url(url.to_string()).await?
+    } else {
+        let account = args.account.expect("required by clap");
+        let secret = read_secret("Secret: ")?;
+        auth2
+            .add_token(
+                account,
+                args.service,
+                secret.to_string(),
+                args.algorithm.map(Into::into),
+                args.digits,
+                args.period,
+            )
+            .await?
+    };
+
+    eprintln!(
+        "added {}",
+        display_name(&token.account, token.service.as_deref())
+    );
+    Ok(())
+}
+
+async fn list(auth2: &Auth2) -> anyhow::Result<()> {
+    for token in auth2.list_tokens(None).await? {
+        println!(
+            "{:<20} {}{}",
+            token.id,
+            display_name(&token.account, token.service.as_deref()),
+            if token.pinned { " (pinned)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+async fn code(auth2: &Auth2, name: &str) -> anyhow::Result<()> {
+    let token = find_token(auth2, name).await?;
+    let result = auth2.generate_current(token.id).await?;
+
+    println!("{}", result.current);
+    if io::stderr().is_terminal() {
+        eprintln!("expires in {}s", result.expires);
+    }
+    Ok(())
+}
+
+async fn rm(auth2: &Auth2, name: &str, yes: bool) -> anyhow::Result<()> {
+    let token = find_token(auth2, name).await?;
+    let name = display_name(&token.account, token.service.as_deref());
+
+    if !yes {
+        if !io::stdin().is_terminal() {
+            bail!("pass --yes to remove {} without a terminal", name);
+        }
+        eprint!("Remove {}? [y/N] ", name);
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+    }
+
+    auth2.remove_token(token.id).await?;
+    eprintln!("removed {}", name);
+    Ok(())
+}
+
+async fn import(auth2: &Auth2, file: &Path) -> anyhow::Result<()> {
+    let reader: Box<dyn BufRead> = if file == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        let f = File::open(file).with_context(|| format!("failed to open {}", file.display()))?;
+        Box::new(BufReader::new(f))
+    };
+
+    let mut imported = 0;
+    let mut failed = 0;
+    for (n, line) in reader.lines().enumerate() {
+        let line = zeroize::Zeroizing::new(line?);
+        let url = line.trim();
+        if url.is_empty() || url.starts_with('#') {
+            continue;
+        }
+        match auth2.add_token_from_url(url.to_string()).await {
+            Ok(_) => imported += 1,
+            Err(e) => {
+                eprintln!("line {}: {}", n + 1, e);
+                failed += 1;
+            }
+        }
+    }
+
+    eprintln!("imported {} tokens", imported);
+    if failed > 0 {
+        bail!("{} lines could not be imported", failed);
+    }
+    Ok(())
+}
+
+async fn export(auth2: &Auth2, file: Option<&Path>) -> anyhow::Result<()> {
+    let mut out: Box<dyn Write> = match file {
+        Some(file) => Box::new(create_private(file)?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let tokens = auth2.list_tokens(None).await?;
+    for token in &tokens {
+        let url = auth2.token_url(token.id).await?;
+        writeln!(out, "{}", *url)?;
+    }
+    out.flush()?;
+
+    eprintln!("exported {} tokens, keep the output safe", tokens.len());
+    Ok(())
+}
+
+/// Creates `file` readable by the owner only, as it will contain secrets.
+fn create_private(file: &Path) -> anyhow::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(file)
+        .with_context(|| format!("failed to create {}", file.display()))
+}
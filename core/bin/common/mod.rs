@@ -0,0 +1,99 @@
+//! Shared by the desktop binaries.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail};
+use auth2::{Auth2, Config, EnvKeyStore, KeyStore, PassphraseKeyStore};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+pub mod tokens;
+
+pub fn default_database() -> anyhow::Result<PathBuf> {
+    let dir = dirs::data_dir().ok_or_else(|| anyhow!("no data directory, pass --database"))?;
+    Ok(dir.join("auth2").join("auth2.db"))
+}
+
+/// Everything but what can't be mistaken for URL syntax; sqlx decodes the
+/// rest back into the file name.
+const PATH_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_');
+
+/// The sqlite URL for `database`, percent-encoded so that a `?`, `#` or `%`
+/// in the path isn't read as part of the URL.
+pub fn database_url(database: &Path) -> anyhow::Result<String> {
+    let path = database
+        .to_str()
+        .ok_or_else(|| anyhow!("database path is not UTF-8: {}", database.display()))?;
+    Ok(format!(
+        "sqlite://{}",
+        utf8_percent_encode(path, PATH_ENCODE_SET)
+    ))
+}
+
+/// The passphrase from `AUTH2_PASSPHRASE` if set, otherwise asked for on
+/// the terminal (twice with `confirm`) the first time it's needed.
+pub fn passphrase_key_store(confirm: bool) -> Arc<dyn KeyStore> {
+    let env = EnvKeyStore::default();
+    if env.is_set() {
+        Arc::new(env)
+    } else {
+        Arc::new(PassphraseKeyStore::new(confirm))
+    }
+}
+
+/// Opens an existing database, upgrading it if needed.
+pub async fn open(database: &Path, key_store: Arc<dyn KeyStore>) -> anyhow::Result<Arc<Auth2>> {
+    if !database.exists() {
+        bail!(
+            "no database at {}, run `auth2 init` first",
+            database.display()
+        );
+    }
+
+    let auth2 = Auth2::new(Config {
+        database_url: database_url(database)?,
+        key_store,
+    })
+    .await?;
+    if auth2.db_is_migration_available().await? {
+        eprintln!("upgrading database");
+        auth2.db_run_migration().await?;
+    }
+    Ok(auth2)
+}
+
+pub fn display_name(account: &str, service: Option<&str>) -> String {
+    match service {
+        Some(service) => format!("{}:{}", service, account),
+        None => account.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use auth2::{Auth2, Config, MemoryKeyStore};
+    use tempfile::tempdir;
+
+    use super::database_url;
+
+    #[tokio::test]
+    async fn test_database_url() {
+        let temp_dir = tempdir().unwrap();
+        let database = temp_dir.path().join("a?b#c%20 d.db");
+
+        let auth2 = Auth2::new(Config::new(
+            database_url(&database).unwrap(),
+            MemoryKeyStore::new("test"),
+        ))
+        .await
+        .unwrap();
+        auth2.db_run_migration().await.unwrap();
+        assert!(database.exists());
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::bail;
+use auth2::{Auth2, Token};
+
+use super::display_name;
+
+/// The one token `name` refers to, see `match_tokens`.
+pub async fn find_token(auth2: &Auth2, name: &str) -> anyhow::Result<Token> {
+    let tokens = auth2.list_tokens(None).await?;
+    let mut matches = match_tokens(tokens, name);
+    match matches.len() {
+        0 => bail!("no token matches {}", name),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            let names: Vec<_> = matches
+                .iter()
+                .map(|t| {
+                    format!(
+                        "{} ({})",
+                        display_name(&t.account, t.service.as_deref()),
+                        t.id
+                    )
+                })
+                .collect();
+            bail!(
+                "{} matches several tokens, use the id: {}",
+                name,
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// Tokens `name` refers to: an exact id, account, service or
+/// `service:account` (ignoring case), or else a part of `service:account`.
+pub fn match_tokens(tokens: Vec<Token>, name: &str) -> Vec<Token> {
+    if let Ok(id) = name.parse::<u64>() {
+        if tokens.iter().any(|t| t.id == id) {
+            return tokens.into_iter().filter(|t| t.id == id).collect();
+        }
+    }
+
+    let name = name.to_lowercase();
+    let (exact, rest): (Vec<_>, Vec<_>) = tokens.into_iter().partition(|t| {
+        t.account.to_lowercase() == name
+            || t.service.as_deref().map(str::to_lowercase).as_deref() == Some(&name)
+            || display_name(&t.account, t.service.as_deref()).to_lowercase() == name
+    });
+    if !exact.is_empty() {
+        return exact;
+    }
+    rest.into_iter()
+        .filter(|t| {
+            display_name(&t.account, t.service.as_deref())
+                .to_lowercase()
+                .contains(&name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use auth2::Token;
+
+    use super::match_tokens;
+
+    fn token(id: u64, account: &str, service: Option<&str>) -> Token {
+        Token {
+            id,
+            account: account.into(),
+            service: service.map(Into::into),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn test_match_tokens() {
+        let tokens = || {
+            vec![
+                token(1, "dameleon", Some("GitHub")),
+                token(2, "dameleon", Some("GitLab")),
+                token(3, "typester", Some("GitHub")),
+            ]
+        };
+        let ids = |name: &str| -> Vec<u64> {
+            match_tokens(tokens(), name).iter().map(|t| t.id).collect()
+        };
+
+        assert_eq!(ids("2"), [2]);
+        assert_eq!(ids("github:dameleon"), [1]);
+        assert_eq!(ids("typester"), [3]);
+        assert_eq!(ids("github"), [1, 3]);
+        assert_eq!(ids("lab"), [2]);
+        assert_eq!(ids("nothing"), Vec::<u64>::new());
+    }
+}
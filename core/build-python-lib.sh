@@ -0,0 +1,8 @@
+#!/bin/sh
+
+set -e
+
+cargo build --lib --release
+cp target/release/libauth2.so ../python/
+
+cargo run --features=uniffi-cli --bin uniffi-bindgen -- generate -l python --library target/release/libauth2.so --out-dir ../python
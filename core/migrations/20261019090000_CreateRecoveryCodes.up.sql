@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE recovery_codes (
+  id INTEGER NOT NULL PRIMARY KEY,
+  token_id INTEGER NOT NULL REFERENCES tokens(id) ON DELETE CASCADE,
+  code TEXT NOT NULL,
+  used_at INTEGER
+);
+
+CREATE INDEX recovery_codes_token_id ON recovery_codes(token_id);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE tokens DROP COLUMN use_count;
+ALTER TABLE tokens DROP COLUMN last_used_at;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE tokens ADD COLUMN last_used_at INTEGER;
+ALTER TABLE tokens ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
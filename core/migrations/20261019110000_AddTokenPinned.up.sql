@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE tokens ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
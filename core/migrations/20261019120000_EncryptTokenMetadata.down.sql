@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX tokens_account_index;
+
+ALTER TABLE tokens DROP COLUMN metadata_encrypted;
+ALTER TABLE tokens DROP COLUMN service_index;
+ALTER TABLE tokens DROP COLUMN account_index;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE tokens ADD COLUMN account_index TEXT;
+ALTER TABLE tokens ADD COLUMN service_index TEXT;
+ALTER TABLE tokens ADD COLUMN metadata_encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX tokens_account_index ON tokens(account_index, service_index);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+-- Vaults created with key slots can't be read without them.
+DROP TABLE key_slots;
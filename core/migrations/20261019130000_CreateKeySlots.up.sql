@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE key_slots (
+  id INTEGER NOT NULL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  label TEXT,
+  iterations INTEGER NOT NULL,
+  wrapped_key TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
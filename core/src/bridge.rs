@@ -1,6 +1,12 @@
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 
-use crate::{config::Config, db::tokens, error::Error, rt, Auth2};
+use crate::{
+    config::{AsyncKeyStore, Config},
+    db::{key_slots, migrate, snapshot, tokens},
+    error::Error,
+    runtime::{block_on, spawn},
+    Auth2,
+};
 
 #[derive(uniffi::Object)]
 pub struct Auth2Bridge {
@@ -11,27 +17,88 @@ pub struct Auth2Bridge {
 impl Auth2Bridge {
     #[uniffi::constructor]
     pub fn new(config: Config) -> Result<Arc<Self>, Error> {
-        rt().block_on(async move {
-            let inner = Auth2::new(config).await?;
-            Ok(Arc::new(Self { inner }))
-        })
+        let inner = block_on(Auth2::new(config))??;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    #[uniffi::constructor]
+    pub async fn new_async(config: Config) -> Result<Arc<Self>, Error> {
+        let inner = spawn(Auth2::new(config)).await??;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    #[uniffi::constructor]
+    pub async fn with_async_key_store(
+        database_url: String,
+        key_store: Arc<dyn AsyncKeyStore>,
+    ) -> Result<Arc<Self>, Error> {
+        let inner = spawn(Auth2::with_async_key_store(database_url, key_store)).await??;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    /// Locks the vault and closes the database. Every call after this fails.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.shutdown().await }).await
+    }
+
+    pub async fn unlock(&self) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.unlock().await }).await?
+    }
+
+    pub async fn lock(&self) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.lock().await }).await
     }
 
     pub async fn db_is_migration_available(&self) -> Result<bool, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.db_is_migration_available().await })
-            .await?
+        spawn(async move { inner.db_is_migration_available().await }).await?
     }
 
     pub async fn db_run_migration(&self) -> Result<(), Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.db_run_migration().await })
-            .await?
+        spawn(async move { inner.db_run_migration().await }).await?
     }
 
-    pub async fn db_reset(&self) -> Result<(), Error> {
+    pub async fn db_migration_status(&self) -> Result<Vec<MigrationStatus>, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.db_reset().await }).await?
+        spawn(async move { inner.db_migration_status().await }).await?
+    }
+
+    pub async fn db_rollback_to(&self, version: i64) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.db_rollback_to(version).await }).await?
+    }
+
+    pub async fn db_repair_migrations(&self) -> Result<Vec<i64>, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.db_repair_migrations().await }).await?
+    }
+
+    pub async fn db_list_snapshots(&self) -> Result<Vec<Snapshot>, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.db_list_snapshots().await }).await?
+    }
+
+    pub async fn db_restore_snapshot(&self, name: String) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.db_restore_snapshot(name).await }).await?
+    }
+
+    pub async fn export_diagnostics(&self, path: String) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.export_diagnostics(path).await }).await?
+    }
+
+    pub fn db_reset_token(&self) -> String {
+        self.inner.db_reset_token()
+    }
+
+    pub async fn db_reset(&self, confirmation: String) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.db_reset(confirmation).await }).await?
     }
 
     pub async fn add_token(
@@ -44,7 +111,7 @@ impl Auth2Bridge {
         period: Option<u32>,
     ) -> Result<TokenDetail, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move {
+        spawn(async move {
             inner
                 .add_token(account, service, secret, algorithm, digits, period)
                 .await
@@ -54,31 +121,101 @@ impl Auth2Bridge {
 
     pub async fn add_token_from_url(&self, url: String) -> Result<TokenDetail, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.add_token_from_url(url).await })
-            .await?
+        spawn(async move { inner.add_token_from_url(url).await }).await?
     }
 
     pub async fn remove_token(&self, id: u64) -> Result<(), Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.remove_token(id).await })
-            .await?
+        spawn(async move { inner.remove_token(id).await }).await?
+    }
+
+    #[uniffi::method(default(order = None))]
+    pub async fn list_tokens(&self, order: Option<TokenOrder>) -> Result<Vec<Token>, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.list_tokens(order).await }).await?
     }
 
-    pub async fn list_tokens(&self) -> Result<Vec<Token>, Error> {
+    #[uniffi::method(default(service = None))]
+    pub async fn find_tokens(
+        &self,
+        account: String,
+        service: Option<String>,
+    ) -> Result<Vec<Token>, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.list_tokens().await }).await?
+        spawn(async move { inner.find_tokens(account, service).await }).await?
     }
 
     pub async fn token_detail(&self, id: u64) -> Result<Option<TokenDetail>, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.token_detail(id).await })
-            .await?
+        spawn(async move { inner.token_detail(id).await }).await?
     }
 
     pub async fn generate_current(&self, id: u64) -> Result<TokenResult, Error> {
         let inner = self.inner.clone();
-        rt().spawn(async move { inner.generate_current(id).await })
-            .await?
+        spawn(async move { inner.generate_current(id).await }).await?
+    }
+
+    /// Records a use of the token that happened outside of `generate_current`,
+    /// e.g. the UI copying the displayed code to the clipboard.
+    pub async fn record_token_use(&self, id: u64) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.record_token_use(id).await }).await?
+    }
+
+    pub async fn set_pinned(&self, id: u64, pinned: bool) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.set_pinned(id, pinned).await }).await?
+    }
+
+    pub async fn add_recovery_codes(
+        &self,
+        token_id: u64,
+        codes: Vec<String>,
+    ) -> Result<Vec<RecoveryCode>, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.add_recovery_codes(token_id, codes).await }).await?
+    }
+
+    pub async fn use_recovery_code(&self, id: u64) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.use_recovery_code(id).await }).await?
+    }
+
+    pub async fn list_recovery_codes(&self, token_id: u64) -> Result<Vec<RecoveryCode>, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.list_recovery_codes(token_id).await }).await?
+    }
+
+    pub async fn list_key_slots(&self) -> Result<Vec<KeySlot>, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.list_key_slots().await }).await?
+    }
+
+    #[uniffi::method(default(label = None))]
+    pub async fn add_key_slot(
+        &self,
+        kind: KeySlotKind,
+        secret: Vec<u8>,
+        label: Option<String>,
+    ) -> Result<KeySlot, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.add_key_slot(kind, secret, label).await }).await?
+    }
+
+    #[uniffi::method(default(label = None))]
+    pub async fn add_recovery_key(&self, label: Option<String>) -> Result<NewRecoveryKey, Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.add_recovery_key(label).await }).await?
+    }
+
+    pub async fn revoke_key_slot(&self, id: u64) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.revoke_key_slot(id).await }).await?
+    }
+
+    pub async fn unlock_with_recovery_key(&self, recovery_key: String) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        spawn(async move { inner.unlock_with_recovery_key(recovery_key).await }).await?
     }
 }
 
@@ -87,6 +224,7 @@ pub struct Token {
     pub id: u64,
     pub account: String,
     pub service: Option<String>,
+    pub pinned: bool,
 }
 
 impl From<tokens::TokenListItem> for Token {
@@ -95,6 +233,7 @@ impl From<tokens::TokenListItem> for Token {
             id: v.id,
             account: v.account,
             service: v.service,
+            pinned: v.pinned,
         }
     }
 }
@@ -107,6 +246,9 @@ pub struct TokenDetail {
     pub algorithm: TokenAlg,
     pub digits: u8,
     pub period: u32,
+    pub last_used_at: Option<u64>,
+    pub use_count: u64,
+    pub pinned: bool,
 }
 
 impl From<tokens::Token> for TokenDetail {
@@ -118,6 +260,9 @@ impl From<tokens::Token> for TokenDetail {
             algorithm: v.data.algorithm.into(),
             digits: v.data.digits,
             period: v.data.period,
+            last_used_at: v.last_used_at,
+            use_count: v.use_count,
+            pinned: v.pinned,
         }
     }
 }
@@ -139,8 +284,129 @@ impl From<tokens::TokenAlg> for TokenAlg {
     }
 }
 
+#[derive(Debug, uniffi::Enum)]
+pub enum TokenOrder {
+    Created,
+    RecentlyUsed,
+}
+
+impl From<TokenOrder> for tokens::TokenOrder {
+    fn from(v: TokenOrder) -> Self {
+        match v {
+            TokenOrder::Created => Self::Created,
+            TokenOrder::RecentlyUsed => Self::RecentlyUsed,
+        }
+    }
+}
+
 #[derive(Debug, uniffi::Record)]
 pub struct TokenResult {
     pub current: String,
     pub expires: u32,
 }
+
+#[derive(Debug, uniffi::Record)]
+pub struct RecoveryCode {
+    pub id: u64,
+    pub code: String,
+}
+
+/// One way of unlocking the vault. The secret itself is never returned.
+#[derive(Debug, uniffi::Record)]
+pub struct KeySlot {
+    pub id: u64,
+    pub kind: KeySlotKind,
+    pub label: Option<String>,
+    pub created_at: u64,
+}
+
+impl From<key_slots::KeySlot> for KeySlot {
+    fn from(v: key_slots::KeySlot) -> Self {
+        Self {
+            id: v.id,
+            kind: v.data.kind.into(),
+            label: v.data.label,
+            created_at: v.created_at,
+        }
+    }
+}
+
+#[derive(Debug, uniffi::Enum)]
+pub enum KeySlotKind {
+    Passphrase,
+    PlatformKey,
+    RecoveryKey,
+}
+
+impl From<key_slots::KeySlotKind> for KeySlotKind {
+    fn from(v: key_slots::KeySlotKind) -> Self {
+        match v {
+            key_slots::KeySlotKind::Passphrase => Self::Passphrase,
+            key_slots::KeySlotKind::PlatformKey => Self::PlatformKey,
+            key_slots::KeySlotKind::RecoveryKey => Self::RecoveryKey,
+        }
+    }
+}
+
+impl From<KeySlotKind> for key_slots::KeySlotKind {
+    fn from(v: KeySlotKind) -> Self {
+        match v {
+            KeySlotKind::Passphrase => Self::Passphrase,
+            KeySlotKind::PlatformKey => Self::PlatformKey,
+            KeySlotKind::RecoveryKey => Self::RecoveryKey,
+        }
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct NewRecoveryKey {
+    pub slot: KeySlot,
+    pub recovery_key: String,
+}
+
+impl fmt::Debug for NewRecoveryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NewRecoveryKey")
+            .field("slot", &self.slot)
+            .field("recovery_key", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, uniffi::Record)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub checksum: String,
+    pub applied: bool,
+    pub mismatched: bool,
+}
+
+impl From<migrate::MigrationStatus> for MigrationStatus {
+    fn from(v: migrate::MigrationStatus) -> Self {
+        Self {
+            version: v.version,
+            description: v.description,
+            checksum: v.checksum,
+            applied: v.applied,
+            mismatched: v.mismatched,
+        }
+    }
+}
+
+#[derive(Debug, uniffi::Record)]
+pub struct Snapshot {
+    pub name: String,
+    pub created_at: u64,
+    pub size: u64,
+}
+
+impl From<snapshot::Snapshot> for Snapshot {
+    fn from(v: snapshot::Snapshot) -> Self {
+        Self {
+            name: v.name,
+            created_at: v.created_at,
+            size: v.size,
+        }
+    }
+}
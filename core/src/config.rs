@@ -1,12 +1,85 @@
 use std::{fmt::Debug, sync::Arc};
 
+use async_trait::async_trait;
+
 #[derive(Debug, uniffi::Record)]
 pub struct Config {
     pub database_url: String,
     pub key_store: Arc<dyn KeyStore>,
 }
 
+impl Config {
+    /// For Rust callers, e.g. with one of the built-in stores:
+    /// `Config::new("sqlite::memory:", MemoryKeyStore::new("key"))`.
+    pub fn new(database_url: impl Into<String>, key_store: impl KeyStore + 'static) -> Self {
+        Self {
+            database_url: database_url.into(),
+            key_store: Arc::new(key_store),
+        }
+    }
+}
+
+/// Why a key store couldn't do what was asked, as opposed to there being
+/// no key.
+#[derive(Debug, uniffi::Error, thiserror::Error)]
+pub enum KeyStoreError {
+    /// The user dismissed a prompt, e.g. for biometrics.
+    #[error("cancelled by the user")]
+    Cancelled,
+
+    /// The key store can't store or delete keys.
+    #[error("not supported by this key store")]
+    Unsupported,
+
+    #[error("key store failure: {0}")]
+    Failed(String),
+}
+
+impl From<uniffi::UnexpectedUniFFICallbackError> for KeyStoreError {
+    fn from(value: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        Self::Failed(value.reason)
+    }
+}
+
+/// Where the user key lives, implemented by the platform.
 #[uniffi::export(with_foreign)]
 pub trait KeyStore: Send + Sync + Debug {
-    fn get(&self) -> Option<String>;
+    /// The stored key, or `None` if there is none yet.
+    fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError>;
+
+    /// Stores `key`, replacing any previous one.
+    fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError>;
+
+    fn delete(&self) -> Result<(), KeyStoreError>;
+}
+
+/// `KeyStore` for platforms where getting at the key means waiting, e.g. on
+/// a biometric prompt, without blocking a thread.
+#[uniffi::export(with_foreign)]
+#[async_trait]
+pub trait AsyncKeyStore: Send + Sync + Debug {
+    async fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError>;
+
+    async fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError>;
+
+    async fn delete(&self) -> Result<(), KeyStoreError>;
+}
+
+/// Lets the core deal with async key stores only.
+#[derive(Debug)]
+pub(crate) struct BlockingKeyStore(pub Arc<dyn KeyStore>);
+
+#[async_trait]
+impl AsyncKeyStore for BlockingKeyStore {
+    async fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+        self.0.get()
+    }
+
+    async fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError> {
+        self.0.set(key)
+    }
+
+    async fn delete(&self) -> Result<(), KeyStoreError> {
+        self.0.delete()
+    }
 }
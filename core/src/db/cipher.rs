@@ -0,0 +1,120 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection};
+
+use super::migrate::{database_files, wipe_file};
+
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Whether `path` is an unencrypted SQLite database. SQLCipher databases
+/// start with a random salt instead of the SQLite header.
+pub(super) fn is_plaintext(path: &Path) -> io::Result<bool> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    let mut header = [0u8; 16];
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(&header == SQLITE_HEADER),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Exports the plaintext database at `path` into a new SQLCipher database
+/// and swaps it in, wiping the plaintext file.
+pub(super) async fn encrypt_in_place(path: &Path, key: &str) -> anyhow::Result<()> {
+    tracing::info!("encrypting plaintext database");
+
+    let mut encrypted = path.as_os_str().to_owned();
+    encrypted.push(".encrypting");
+    let encrypted = Path::new(&encrypted).to_path_buf();
+    // leftover from an interrupted attempt
+    if encrypted.exists() {
+        fs::remove_file(&encrypted)?;
+    }
+
+    // ATTACH opens the new file with the flags of this connection
+    let mut conn = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .connect()
+        .await?;
+    sqlx::query(&format!("ATTACH DATABASE ? AS encrypted KEY {}", key))
+        .bind(encrypted.to_string_lossy().into_owned())
+        .execute(&mut conn)
+        .await?;
+    sqlx::query("SELECT sqlcipher_export('encrypted')")
+        .execute(&mut conn)
+        .await?;
+    sqlx::query("DETACH DATABASE encrypted")
+        .execute(&mut conn)
+        .await?;
+    conn.close().await?;
+
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || -> io::Result<()> {
+        for file in database_files(&path) {
+            wipe_file(&file)?;
+        }
+        fs::rename(&encrypted, &path)
+    })
+    .await??;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use super::is_plaintext;
+    use crate::{
+        db::{
+            migrate::MigrateDatabase,
+            tokens::{TokenData, TokenOrder, TokensDatabase},
+            Database, Db,
+        },
+        enc::derive_database_key,
+    };
+
+    #[tokio::test]
+    async fn test_encrypt_existing_database() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("database.db");
+        let database_url = format!("sqlite://{}", path.to_str().unwrap());
+
+        let db = Db::new(database_url.clone()).unwrap();
+        db.run_migration().await.unwrap();
+        db.add_token(TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        db.pool().close().await;
+        assert!(is_plaintext(&path).unwrap());
+
+        let key = derive_database_key(b"test");
+        let db: Arc<dyn Database> = Db::new_encrypted(database_url.clone(), key).await.unwrap();
+        assert!(!is_plaintext(&path).unwrap());
+        assert!(!db.is_migration_available().await.unwrap());
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].account, "dameleon");
+
+        let db: Arc<dyn Database> = Db::new_encrypted(database_url, derive_database_key(b"test?"))
+            .await
+            .unwrap();
+        assert!(db.list_tokens(TokenOrder::Created).await.is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+use super::Db;
+
+/// The migration that creates `key_slots`. Rolling it back leaves tokens
+/// encrypted with the user key readable, but not ones under a random data
+/// key.
+pub const MIGRATION_VERSION: i64 = 20261019130000;
+
+/// The vault data key, wrapped with a key derived from one unlock method's
+/// secret.
+#[derive(Debug, sqlx::FromRow)]
+pub struct KeySlot {
+    pub id: u64,
+    #[sqlx(flatten)]
+    pub data: KeySlotData,
+    pub created_at: u64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct KeySlotData {
+    #[sqlx(json)]
+    pub kind: KeySlotKind,
+    pub label: Option<String>,
+    /// PBKDF2 iterations for the wrapping key, kept per slot so they can be
+    /// raised for new slots later.
+    pub iterations: u32,
+    pub wrapped_key: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum KeySlotKind {
+    Passphrase,
+    PlatformKey,
+    RecoveryKey,
+}
+
+#[async_trait]
+pub trait KeySlotsDatabase {
+    async fn add_key_slot(&self, slot: KeySlotData) -> Result<KeySlot, Error>;
+    /// Adds `slot` only if there are no slots yet, so two processes can't
+    /// each set up a data key. Returns `None` if there were.
+    async fn add_first_key_slot(&self, slot: KeySlotData) -> Result<Option<KeySlot>, Error>;
+    async fn list_key_slots(&self) -> Result<Vec<KeySlot>, Error>;
+    /// Removes a slot unless it's the last one `unlock` can use, i.e. the
+    /// last one that isn't a recovery key, which would leave only recovery
+    /// keys to open the vault. Returns whether it was removed.
+    async fn remove_key_slot(&self, id: u64) -> Result<bool, Error>;
+    /// Something encrypted before there were key slots, if anything was, to
+    /// check a key against.
+    async fn encrypted_sample(&self) -> Result<Option<String>, Error>;
+}
+
+impl Db {
+    async fn insert_key_slot(
+        &self,
+        slot: KeySlotData,
+        first: bool,
+    ) -> Result<Option<KeySlot>, Error> {
+        let id = self.next_id().await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(anyhow::Error::from)?
+            .as_secs();
+
+        let query = if first {
+            "INSERT INTO key_slots (id, kind, label, iterations, wrapped_key, created_at) SELECT ?, ?, ?, ?, ?, ? WHERE NOT EXISTS (SELECT 1 FROM key_slots)"
+        } else {
+            "INSERT INTO key_slots (id, kind, label, iterations, wrapped_key, created_at) VALUES (?, ?, ?, ?, ?, ?)"
+        };
+        let res = sqlx::query(query)
+            .bind(id as i64)
+            .bind(serde_json::to_string(&slot.kind)?)
+            .bind(&slot.label)
+            .bind(slot.iterations)
+            .bind(&slot.wrapped_key)
+            .bind(now as i64)
+            .execute(&self.pool())
+            .await?;
+        Ok((res.rows_affected() > 0).then_some(KeySlot {
+            id,
+            data: slot,
+            created_at: now,
+        }))
+    }
+}
+
+#[async_trait]
+impl KeySlotsDatabase for Db {
+    async fn add_key_slot(&self, slot: KeySlotData) -> Result<KeySlot, Error> {
+        match self.insert_key_slot(slot, false).await? {
+            Some(slot) => Ok(slot),
+            None => Err(Error::InternalError("failed to add key slot".into())),
+        }
+    }
+
+    async fn add_first_key_slot(&self, slot: KeySlotData) -> Result<Option<KeySlot>, Error> {
+        self.insert_key_slot(slot, true).await
+    }
+
+    async fn list_key_slots(&self) -> Result<Vec<KeySlot>, Error> {
+        let slots: Vec<KeySlot> = sqlx::query_as("SELECT * FROM key_slots ORDER BY id")
+            .fetch_all(&self.pool())
+            .await?;
+        Ok(slots)
+    }
+
+    async fn remove_key_slot(&self, id: u64) -> Result<bool, Error> {
+        let res = sqlx::query(
+            "DELETE FROM key_slots WHERE id = ?1 AND (kind = ?2 OR (SELECT COUNT(*) FROM key_slots WHERE kind != ?2) > 1)",
+        )
+        .bind(id as i64)
+        .bind(serde_json::to_string(&KeySlotKind::RecoveryKey)?)
+        .execute(&self.pool())
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn encrypted_sample(&self) -> Result<Option<String>, Error> {
+        let sample: Option<(String,)> = sqlx::query_as(
+            "SELECT secret FROM tokens UNION ALL SELECT code FROM recovery_codes LIMIT 1",
+        )
+        .fetch_optional(&self.pool())
+        .await?;
+        Ok(sample.map(|(sample,)| sample))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use super::{KeySlotData, KeySlotKind};
+    use crate::db::{tokens::TokenData, Database, Db};
+
+    fn slot(kind: KeySlotKind, wrapped_key: &str) -> KeySlotData {
+        KeySlotData {
+            kind,
+            label: None,
+            iterations: 1,
+            wrapped_key: wrapped_key.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_slots() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.reset_database().await.unwrap();
+        db.run_migration().await.unwrap();
+        assert!(db.encrypted_sample().await.unwrap().is_none());
+
+        let first = db
+            .add_first_key_slot(slot(KeySlotKind::Passphrase, "a"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(db
+            .add_first_key_slot(slot(KeySlotKind::Passphrase, "b"))
+            .await
+            .unwrap()
+            .is_none());
+        let recovery = db
+            .add_key_slot(KeySlotData {
+                label: Some("paper".into()),
+                ..slot(KeySlotKind::RecoveryKey, "c")
+            })
+            .await
+            .unwrap();
+
+        let slots = db.list_key_slots().await.unwrap();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[0].id, first.id);
+        assert_eq!(slots[0].data.wrapped_key, "a");
+        assert_eq!(slots[1].data.kind, KeySlotKind::RecoveryKey);
+        assert_eq!(slots[1].data.label.as_deref(), Some("paper"));
+        assert_eq!(slots[1].created_at, recovery.created_at);
+
+        // the last one that isn't a recovery key stays
+        assert!(!db.remove_key_slot(first.id).await.unwrap());
+        let platform = db
+            .add_key_slot(slot(KeySlotKind::PlatformKey, "d"))
+            .await
+            .unwrap();
+        assert!(db.remove_key_slot(first.id).await.unwrap());
+        assert!(!db.remove_key_slot(platform.id).await.unwrap());
+        assert!(db.remove_key_slot(recovery.id).await.unwrap());
+        assert_eq!(db.list_key_slots().await.unwrap().len(), 1);
+
+        db.add_token(TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            db.encrypted_sample().await.unwrap().as_deref(),
+            Some("hoge")
+        );
+    }
+}
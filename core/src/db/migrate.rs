@@ -1,71 +1,463 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
 use anyhow::bail;
 use async_trait::async_trait;
-use sqlx::{migrate::Migrate, Connection, SqliteConnection};
-use tokio::fs::File;
+use sqlx::{
+    migrate::{AppliedMigration, Migrate},
+    Connection, SqliteConnection,
+};
 
-use super::Db;
+use super::{snapshot::SnapshotDatabase, Db};
+
+/// Adds the encrypted account and service columns. Its down migration can't
+/// decrypt them, so it would leave ciphertext behind as plain metadata.
+const ENCRYPT_TOKEN_METADATA_VERSION: i64 = 20261019120000;
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub checksum: String,
+    pub applied: bool,
+    /// The migration was applied with a different checksum than the one
+    /// embedded in this build.
+    pub mismatched: bool,
+}
+
+/// Applied migrations whose recorded checksum differs from the embedded one,
+/// usually because a migration script was edited after it shipped.
+#[derive(Debug, thiserror::Error)]
+#[error("mismatched migrations are found: {versions:?}")]
+pub struct MismatchedMigrations {
+    pub versions: Vec<i64>,
+}
 
 #[async_trait]
 pub trait MigrateDatabase {
+    /// Fails with [`MismatchedMigrations`] if any applied migration doesn't
+    /// match its embedded checksum.
     async fn is_migration_available(&self) -> anyhow::Result<bool>;
     async fn run_migration(&self) -> anyhow::Result<()>;
     async fn reset_database(&self) -> anyhow::Result<()>;
+    async fn migration_status(&self) -> anyhow::Result<Vec<MigrationStatus>>;
+    async fn rollback_to(&self, version: i64) -> anyhow::Result<()>;
+    async fn repair_migrations(&self) -> anyhow::Result<Vec<i64>>;
 }
 
 #[async_trait]
 impl MigrateDatabase for Db {
     async fn is_migration_available(&self) -> anyhow::Result<bool> {
         tracing::debug!("checking available migrations");
-        let migrator = sqlx::migrate!();
-        // Seems like the SqlitePool doesn't implement Migrate trait
-        let mut conn = SqliteConnection::connect(&self.database_url).await?;
+        let status = self.migration_status().await?;
 
-        conn.ensure_migrations_table().await?;
-
-        let applied_migrations: HashMap<_, _> = conn
-            .list_applied_migrations()
-            .await?
-            .into_iter()
-            .map(|m| (m.version, m))
+        let versions: Vec<i64> = status
+            .iter()
+            .filter(|m| m.mismatched)
+            .map(|m| m.version)
             .collect();
+        if !versions.is_empty() {
+            tracing::error!(?versions, "mismatched migration is found");
+            return Err(MismatchedMigrations { versions }.into());
+        }
 
-        let has_mismatched_migration = migrator
-            .iter()
-            .filter(|m| !m.migration_type.is_down_migration())
-            .find(|m| {
-                if let Some(applied) = applied_migrations.get(&m.version) {
-                    m.checksum != applied.checksum
-                } else {
-                    false
+        Ok(status.iter().any(|m| !m.applied))
+    }
+
+    /// Applies pending migrations. If the database already holds data, a
+    /// snapshot is taken first and restored when any migration fails.
+    async fn run_migration(&self) -> anyhow::Result<()> {
+        let migrator = sqlx::migrate!();
+
+        let status = self.migration_status().await?;
+        let snapshot = if status.iter().any(|m| m.applied) && status.iter().any(|m| !m.applied) {
+            self.create_snapshot().await?
+        } else {
+            None
+        };
+
+        if let Err(e) = migrator.run(&self.pool()).await {
+            if let Some(snapshot) = snapshot {
+                tracing::error!(error = %e, snapshot = snapshot.name, "migration failed, restoring snapshot");
+                if let Err(restore_error) = self.restore_snapshot(snapshot.name).await {
+                    tracing::error!(error = %restore_error, "failed to restore snapshot");
                 }
-            });
-        if has_mismatched_migration.is_some() {
-            bail!("mismatched migration is found");
+            }
+            return Err(e.into());
         }
+        Ok(())
+    }
 
-        let has_unapplied_migration = match migrator
+    async fn migration_status(&self) -> anyhow::Result<Vec<MigrationStatus>> {
+        let migrator = sqlx::migrate!();
+        let applied_migrations = self.applied_migrations().await?;
+
+        Ok(migrator
             .iter()
             .filter(|m| !m.migration_type.is_down_migration())
-            .find(|m| !applied_migrations.contains_key(&m.version))
+            .map(|m| {
+                let applied = applied_migrations.get(&m.version);
+                MigrationStatus {
+                    version: m.version,
+                    description: m.description.to_string(),
+                    checksum: m.checksum.iter().map(|b| format!("{:02x}", b)).collect(),
+                    applied: applied.is_some(),
+                    mismatched: applied.is_some_and(|applied| applied.checksum != m.checksum),
+                }
+            })
+            .collect())
+    }
+
+    /// Reverts every applied migration newer than `version`. Passing `0`
+    /// reverts all of them.
+    async fn rollback_to(&self, version: i64) -> anyhow::Result<()> {
+        let migrator = sqlx::migrate!();
+        if version != 0 && !migrator.iter().any(|m| m.version == version) {
+            bail!("unknown migration version: {}", version);
+        }
+        if version < ENCRYPT_TOKEN_METADATA_VERSION
+            && self
+                .applied_migrations()
+                .await?
+                .contains_key(&ENCRYPT_TOKEN_METADATA_VERSION)
         {
-            Some(_) => true,
-            None => false,
-        };
+            let (encrypted,): (bool,) =
+                sqlx::query_as("SELECT EXISTS (SELECT 1 FROM tokens WHERE metadata_encrypted)")
+                    .fetch_one(&self.pool())
+                    .await?;
+            if encrypted {
+                bail!(
+                    "tokens have encrypted metadata, can't roll back below {}",
+                    ENCRYPT_TOKEN_METADATA_VERSION
+                );
+            }
+        }
 
-        Ok(has_unapplied_migration)
+        tracing::info!(version, "rolling back migrations");
+        Ok(migrator.undo(&self.pool(), version).await?)
     }
 
-    async fn run_migration(&self) -> anyhow::Result<()> {
+    /// Re-records the checksums of mismatched migrations, but only after
+    /// checking that the current schema is the one the embedded migrations
+    /// would produce. Returns the repaired versions.
+    async fn repair_migrations(&self) -> anyhow::Result<Vec<i64>> {
         let migrator = sqlx::migrate!();
-        Ok(migrator.run(&self.pool).await?)
+        let applied_migrations = self.applied_migrations().await?;
+
+        let applied: Vec<_> = migrator
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .filter(|m| applied_migrations.contains_key(&m.version))
+            .collect();
+        let mismatched: Vec<_> = applied
+            .iter()
+            .filter(|m| applied_migrations[&m.version].checksum != m.checksum)
+            .collect();
+        if mismatched.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut expected = SqliteConnection::connect("sqlite::memory:").await?;
+        expected.ensure_migrations_table().await?;
+        for m in &applied {
+            expected.apply(m).await?;
+        }
+
+        let mut conn = self.pool().acquire().await?;
+        if Schema::read(&mut conn).await? != Schema::read(&mut expected).await? {
+            bail!("database schema doesn't match the migrations, can't repair checksums");
+        }
+
+        let mut versions = vec![];
+        for m in mismatched {
+            tracing::info!(version = m.version, "re-recording migration checksum");
+            let _ = sqlx::query("UPDATE _sqlx_migrations SET checksum = ? WHERE version = ?")
+                .bind(&*m.checksum)
+                .bind(m.version)
+                .execute(&mut *conn)
+                .await?;
+            versions.push(m.version);
+        }
+
+        Ok(versions)
     }
 
     async fn reset_database(&self) -> anyhow::Result<()> {
-        let path = Path::new(&self.database_url["sqlite://".len()..]);
-        let file = File::create(path).await?;
-        file.set_len(0).await?;
-        Ok(())
+        tracing::info!("resetting database");
+        let path = self.database_path();
+        let snapshots = self.list_snapshots().await?;
+
+        // Make sure no connection keeps the old file (or its WAL) open while
+        // it is being wiped.
+        self.pool().close().await;
+
+        if let Some(path) = path {
+            tokio::task::spawn_blocking(move || -> io::Result<()> {
+                for file in database_files(&path) {
+                    wipe_file(&file)?;
+                }
+                for snapshot in snapshots {
+                    wipe_file(&path.with_file_name(snapshot.name))?;
+                }
+                Ok(())
+            })
+            .await??;
+        }
+
+        self.reopen_pool()
+    }
+}
+
+impl Db {
+    async fn applied_migrations(&self) -> anyhow::Result<HashMap<i64, AppliedMigration>> {
+        // Seems like the SqlitePool doesn't implement Migrate trait
+        let mut conn = self.pool().acquire().await?;
+
+        conn.ensure_migrations_table().await?;
+
+        Ok(conn
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|m| (m.version, m))
+            .collect())
+    }
+}
+
+/// Tables, columns and indexes of a database, ignoring how the statements
+/// that created them were formatted.
+#[derive(Debug, PartialEq)]
+struct Schema {
+    columns: Vec<(String, String, String, bool, Option<String>, i64)>,
+    indexes: Vec<(String, String)>,
+}
+
+impl Schema {
+    async fn read(conn: &mut SqliteConnection) -> anyhow::Result<Self> {
+        let columns = sqlx::query_as(
+            "SELECT m.name, p.name, p.type, p.\"notnull\", p.dflt_value, p.pk \
+             FROM sqlite_master m JOIN pragma_table_info(m.name) p \
+             WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%' AND m.name != '_sqlx_migrations' \
+             ORDER BY m.name, p.cid",
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        let indexes = sqlx::query_as(
+            "SELECT name, tbl_name FROM sqlite_master \
+             WHERE type = 'index' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(Self { columns, indexes })
+    }
+}
+
+/// The database file along with the journal files SQLite keeps next to it.
+pub(super) fn database_files(path: &Path) -> Vec<PathBuf> {
+    ["", "-wal", "-shm", "-journal"]
+        .iter()
+        .map(|suffix| {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            PathBuf::from(file)
+        })
+        .collect()
+}
+
+/// Overwrites the file with zeros before removing it, so the freed blocks
+/// don't keep encrypted secrets or account names around. This is best-effort
+/// on flash storage, where the filesystem may remap blocks on write.
+pub(super) fn wipe_file(path: &Path) -> io::Result<()> {
+    let mut file = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let zeros = [0u8; 64 * 1024];
+    let mut remaining = file.metadata()?.len();
+    while remaining > 0 {
+        let n = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        remaining -= n as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use super::{MigrateDatabase, MismatchedMigrations};
+    use crate::db::{tokens::TokenData, tokens::TokenOrder, Database, Db};
+
+    #[tokio::test]
+    async fn test_reset_database() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("database.db");
+        let database_url = format!("sqlite://{}", path.to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.reset_database().await.unwrap();
+        db.run_migration().await.unwrap();
+
+        db.add_token(TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        db.reset_database().await.unwrap();
+        assert!(!path.exists());
+        assert!(!temp_dir.path().join("database.db-wal").exists());
+
+        assert!(db.is_migration_available().await.unwrap());
+        db.run_migration().await.unwrap();
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        assert_eq!(tokens.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_database() {
+        let db: Arc<dyn Database> = Db::new("sqlite::memory:".into()).unwrap();
+        assert!(db.is_migration_available().await.unwrap());
+        db.run_migration().await.unwrap();
+        assert!(!db.is_migration_available().await.unwrap());
+
+        db.add_token(TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+
+        db.reset_database().await.unwrap();
+        assert!(db.is_migration_available().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let db: Arc<dyn Database> = Db::new("sqlite::memory:".into()).unwrap();
+
+        let status = db.migration_status().await.unwrap();
+        assert!(status.len() > 1);
+        assert!(status.iter().all(|m| !m.applied));
+        assert_eq!(status[0].description, "CreateTable");
+        assert_eq!(status[0].checksum.len(), 96);
+
+        db.run_migration().await.unwrap();
+        let status = db.migration_status().await.unwrap();
+        assert!(status.iter().all(|m| m.applied));
+
+        let first = status[0].version;
+        db.rollback_to(first).await.unwrap();
+        let status = db.migration_status().await.unwrap();
+        assert!(status[0].applied);
+        assert!(status[1..].iter().all(|m| !m.applied));
+        assert!(db.is_migration_available().await.unwrap());
+
+        db.run_migration().await.unwrap();
+        assert!(!db.is_migration_available().await.unwrap());
+
+        assert!(db.rollback_to(1).await.is_err());
+        db.rollback_to(0).await.unwrap();
+        let status = db.migration_status().await.unwrap();
+        assert!(status.iter().all(|m| !m.applied));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_with_encrypted_metadata() {
+        let db: Arc<dyn Database> = Db::new("sqlite::memory:".into()).unwrap();
+        db.run_migration().await.unwrap();
+
+        let id = db
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: "hoge".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        db.update_token_metadata(id, "x".into(), None, "y".into(), None)
+            .await
+            .unwrap();
+
+        let first = db.migration_status().await.unwrap()[0].version;
+        assert!(db.rollback_to(first).await.is_err());
+        assert!(!db.is_migration_available().await.unwrap());
+
+        db.remove_token(id).await.unwrap();
+        db.rollback_to(first).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reset_removes_snapshots() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.run_migration().await.unwrap();
+        db.create_snapshot().await.unwrap();
+        assert_eq!(db.list_snapshots().await.unwrap().len(), 1);
+
+        db.reset_database().await.unwrap();
+        assert_eq!(db.list_snapshots().await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_repair_migrations() {
+        let db = Db::new("sqlite::memory:".into()).unwrap();
+        db.run_migration().await.unwrap();
+        assert_eq!(db.repair_migrations().await.unwrap(), Vec::<i64>::new());
+
+        let version = db.migration_status().await.unwrap()[0].version;
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = x'00' WHERE version = ?")
+            .bind(version)
+            .execute(&db.pool())
+            .await
+            .unwrap();
+
+        let err = db.is_migration_available().await.unwrap_err();
+        let err = err.downcast_ref::<MismatchedMigrations>().unwrap();
+        assert_eq!(err.versions, vec![version]);
+        assert!(db.migration_status().await.unwrap()[0].mismatched);
+
+        assert_eq!(db.repair_migrations().await.unwrap(), vec![version]);
+        assert!(!db.is_migration_available().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_repair_migrations_with_different_schema() {
+        let db = Db::new("sqlite::memory:".into()).unwrap();
+        db.run_migration().await.unwrap();
+
+        let version = db.migration_status().await.unwrap()[0].version;
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = x'00' WHERE version = ?")
+            .bind(version)
+            .execute(&db.pool())
+            .await
+            .unwrap();
+        sqlx::query("ALTER TABLE tokens ADD COLUMN note TEXT")
+            .execute(&db.pool())
+            .await
+            .unwrap();
+
+        assert!(db.repair_migrations().await.is_err());
+        assert!(db.is_migration_available().await.is_err());
     }
 }
@@ -1,35 +1,213 @@
-use std::sync::{Arc, LazyLock};
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+};
 
+use anyhow::bail;
+use async_trait::async_trait;
 use frostflake::{GeneratorAsync, GeneratorOptions};
-use sqlx::SqlitePool;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+use zeroize::Zeroizing;
 
+use key_slots::KeySlotsDatabase;
 use migrate::MigrateDatabase;
+use recovery_codes::RecoveryCodesDatabase;
+use snapshot::SnapshotDatabase;
 use tokens::TokensDatabase;
 
+#[cfg(feature = "sqlcipher")]
+mod cipher;
+pub mod key_slots;
 pub mod migrate;
+pub mod recovery_codes;
+pub mod snapshot;
 pub mod tokens;
 
-pub trait Database: Send + Sync + MigrateDatabase + TokensDatabase {}
+#[async_trait]
+pub trait Database:
+    Send
+    + Sync
+    + MigrateDatabase
+    + TokensDatabase
+    + RecoveryCodesDatabase
+    + KeySlotsDatabase
+    + SnapshotDatabase
+{
+    /// Closes the connection pool and stops the id generator.
+    async fn close(&self);
+}
+
+/// The frostflake generator runs as a task on the runtime that first asks
+/// for an id, and stops once it is dropped.
+enum IdGenerator {
+    Idle,
+    Running(Arc<GeneratorAsync>),
+    Stopped,
+}
 
 pub struct Db {
     database_url: String,
-    pool: SqlitePool,
-    id_generator: LazyLock<Arc<GeneratorAsync>>,
+    in_memory: bool,
+    key: Option<Zeroizing<String>>,
+    pool: RwLock<SqlitePool>,
+    id_generator: Mutex<IdGenerator>,
 }
 
 impl Db {
+    /// Opens the database at `database_url`, creating the file if it doesn't
+    /// exist yet. `sqlite::memory:` (or `mode=memory`) opens an ephemeral
+    /// database that lives as long as this `Db`.
+    #[cfg_attr(feature = "sqlcipher", allow(dead_code))]
     pub fn new(database_url: String) -> anyhow::Result<Arc<Self>> {
-        let pool = SqlitePool::connect_lazy(&database_url)?;
+        Self::open(database_url, None)
+    }
+
+    /// Opens a SQLCipher database with `key` (see `enc::derive_database_key`).
+    /// An existing plaintext database is encrypted in place first.
+    #[cfg(feature = "sqlcipher")]
+    pub async fn new_encrypted(
+        database_url: String,
+        key: Zeroizing<String>,
+    ) -> anyhow::Result<Arc<Self>> {
+        if !is_in_memory(&database_url) {
+            let options = SqliteConnectOptions::from_str(&database_url)?;
+            let path = options.get_filename();
+            if cipher::is_plaintext(path)? {
+                cipher::encrypt_in_place(path, &key).await?;
+            }
+        }
+        Self::open(database_url, Some(key))
+    }
+
+    fn open(database_url: String, key: Option<Zeroizing<String>>) -> anyhow::Result<Arc<Self>> {
+        let in_memory = is_in_memory(&database_url);
+        let pool = open_pool(&database_url, in_memory, key.as_ref().map(|k| k.as_str()))?;
         Ok(Arc::new(Self {
             database_url,
-            pool,
-            id_generator: LazyLock::new(|| GeneratorAsync::spawn(GeneratorOptions::default())),
+            in_memory,
+            key,
+            pool: RwLock::new(pool),
+            id_generator: Mutex::new(IdGenerator::Idle),
         }))
     }
 
+    /// Returns the current connection pool. The pool is replaced when the
+    /// database is reset, so callers should not hold on to it.
+    fn pool(&self) -> SqlitePool {
+        self.pool.read().expect("pool lock poisoned").clone()
+    }
+
+    /// Path of the database file, or `None` for an in-memory database.
+    fn database_path(&self) -> Option<PathBuf> {
+        if self.in_memory {
+            return None;
+        }
+        Some(self.pool().connect_options().get_filename().to_path_buf())
+    }
+
+    /// Swaps in a fresh pool after the database file was replaced. The old
+    /// pool must have been closed by the caller.
+    fn reopen_pool(&self) -> anyhow::Result<()> {
+        let pool = open_pool(
+            &self.database_url,
+            self.in_memory,
+            self.key.as_ref().map(|k| k.as_str()),
+        )?;
+        *self.pool.write().expect("pool lock poisoned") = pool;
+        Ok(())
+    }
+
     async fn next_id(&self) -> anyhow::Result<u64> {
-        self.id_generator.generate().await
+        let generator = {
+            let mut id_generator = self
+                .id_generator
+                .lock()
+                .expect("id generator lock poisoned");
+            match &*id_generator {
+                IdGenerator::Idle => {
+                    let generator = GeneratorAsync::spawn(GeneratorOptions::default());
+                    *id_generator = IdGenerator::Running(generator.clone());
+                    generator
+                }
+                IdGenerator::Running(generator) => generator.clone(),
+                IdGenerator::Stopped => bail!("database is closed"),
+            }
+        };
+        generator.generate().await
     }
 }
 
-impl Database for Db {}
+#[async_trait]
+impl Database for Db {
+    async fn close(&self) {
+        *self
+            .id_generator
+            .lock()
+            .expect("id generator lock poisoned") = IdGenerator::Stopped;
+        self.pool().close().await;
+    }
+}
+
+fn open_pool(database_url: &str, in_memory: bool, key: Option<&str>) -> anyhow::Result<SqlitePool> {
+    let mut options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+    if let Some(key) = key {
+        options = options.pragma("key", key.to_owned());
+    }
+
+    let mut pool = SqlitePoolOptions::new();
+    if in_memory {
+        // An in-memory database is gone once its last connection is closed,
+        // and unless sqlx made it shared-cache (only for `:memory:`), every
+        // other connection would open a database of its own.
+        pool = pool
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+    }
+    Ok(pool.connect_lazy_with(options))
+}
+
+fn is_in_memory(database_url: &str) -> bool {
+    let url = database_url.trim_start_matches("sqlite:");
+    let url = url.trim_start_matches("//");
+    let (database, params) = url.split_once('?').unwrap_or((url, ""));
+    database == ":memory:" || params.split('&').any(|p| p == "mode=memory")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{is_in_memory, Database, Db};
+    use crate::db::tokens::{TokenData, TokenOrder};
+
+    #[test]
+    fn test_is_in_memory() {
+        assert!(is_in_memory("sqlite::memory:"));
+        assert!(is_in_memory("sqlite://:memory:"));
+        assert!(is_in_memory("sqlite://?mode=memory"));
+        assert!(is_in_memory("sqlite://?cache=shared&mode=memory"));
+        assert!(!is_in_memory("sqlite:///data/database.db"));
+        assert!(!is_in_memory("sqlite:database.db?mode=rwc"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_mode_url() {
+        let db: Arc<dyn Database> = Db::new("sqlite://?mode=memory".into()).unwrap();
+        db.run_migration().await.unwrap();
+        db.add_token(TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use crate::error::Error;
+
+use super::Db;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RecoveryCode {
+    pub id: u64,
+    pub token_id: u64,
+    pub code: String,
+    pub used_at: Option<u64>,
+}
+
+#[async_trait]
+pub trait RecoveryCodesDatabase {
+    async fn add_recovery_codes(
+        &self,
+        token_id: u64,
+        codes: Vec<String>,
+    ) -> Result<Vec<u64>, Error>;
+    async fn use_recovery_code(&self, id: u64) -> Result<bool, Error>;
+    async fn list_recovery_codes(&self, token_id: u64) -> Result<Vec<RecoveryCode>, Error>;
+}
+
+#[async_trait]
+impl RecoveryCodesDatabase for Db {
+    async fn add_recovery_codes(
+        &self,
+        token_id: u64,
+        codes: Vec<String>,
+    ) -> Result<Vec<u64>, Error> {
+        let mut tx = self.pool().begin().await?;
+        let mut ids = Vec::with_capacity(codes.len());
+
+        for code in codes {
+            let id = self.next_id().await?;
+            let _ = sqlx::query("INSERT INTO recovery_codes (id, token_id, code) VALUES (?, ?, ?)")
+                .bind(id as i64)
+                .bind(token_id as i64)
+                .bind(code)
+                .execute(&mut *tx)
+                .await?;
+            ids.push(id);
+        }
+
+        tx.commit().await?;
+        Ok(ids)
+    }
+
+    async fn use_recovery_code(&self, id: u64) -> Result<bool, Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(anyhow::Error::from)?
+            .as_secs();
+
+        let res =
+            sqlx::query("UPDATE recovery_codes SET used_at = ? WHERE id = ? AND used_at IS NULL")
+                .bind(now as i64)
+                .bind(id as i64)
+                .execute(&self.pool())
+                .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn list_recovery_codes(&self, token_id: u64) -> Result<Vec<RecoveryCode>, Error> {
+        let codes: Vec<RecoveryCode> = sqlx::query_as(
+            "SELECT * FROM recovery_codes WHERE token_id = ? AND used_at IS NULL ORDER BY id",
+        )
+        .bind(token_id as i64)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(codes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use crate::db::{tokens::TokenData, Database, Db};
+
+    #[tokio::test]
+    async fn test_recovery_codes() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.reset_database().await.unwrap();
+        db.run_migration().await.unwrap();
+
+        let token_id = db
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: "hoge".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let ids = db
+            .add_recovery_codes(token_id, vec!["code1".into(), "code2".into()])
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+
+        let codes = db.list_recovery_codes(token_id).await.unwrap();
+        assert_eq!(codes.len(), 2);
+        assert_eq!(codes[0].code, "code1");
+
+        assert!(db.use_recovery_code(ids[0]).await.unwrap());
+        assert!(!db.use_recovery_code(ids[0]).await.unwrap());
+
+        let codes = db.list_recovery_codes(token_id).await.unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].id, ids[1]);
+
+        db.remove_token(token_id).await.unwrap();
+        let codes = db.list_recovery_codes(token_id).await.unwrap();
+        assert_eq!(codes.len(), 0);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    cmp::Reverse,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+
+use super::{migrate::database_files, Db};
+
+/// Number of snapshots kept next to the database; older ones are removed
+/// when a new snapshot is taken.
+const MAX_SNAPSHOTS: usize = 3;
+
+#[derive(Debug)]
+pub struct Snapshot {
+    pub name: String,
+    /// Unix time in milliseconds.
+    pub created_at: u64,
+    pub size: u64,
+}
+
+#[async_trait]
+pub trait SnapshotDatabase {
+    /// Writes a consistent copy of the database next to the database file.
+    /// Returns `None` for in-memory databases.
+    async fn create_snapshot(&self) -> anyhow::Result<Option<Snapshot>>;
+    async fn list_snapshots(&self) -> anyhow::Result<Vec<Snapshot>>;
+    async fn restore_snapshot(&self, name: String) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl SnapshotDatabase for Db {
+    async fn create_snapshot(&self) -> anyhow::Result<Option<Snapshot>> {
+        let Some(path) = self.database_path() else {
+            return Ok(None);
+        };
+
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let name = format!("{}{}", snapshot_prefix(&path)?, created_at);
+        let snapshot_path = path.with_file_name(&name);
+
+        tracing::info!(snapshot = name, "taking database snapshot");
+        let _ = sqlx::query("VACUUM INTO ?")
+            .bind(snapshot_path.to_string_lossy().into_owned())
+            .execute(&self.pool())
+            .await?;
+
+        let snapshots = self.list_snapshots().await?;
+        for old in snapshots.iter().skip(MAX_SNAPSHOTS) {
+            fs::remove_file(path.with_file_name(&old.name))?;
+        }
+
+        let size = fs::metadata(&snapshot_path)?.len();
+        Ok(Some(Snapshot {
+            name,
+            created_at,
+            size,
+        }))
+    }
+
+    /// Lists snapshots, newest first.
+    async fn list_snapshots(&self) -> anyhow::Result<Vec<Snapshot>> {
+        let Some(path) = self.database_path() else {
+            return Ok(vec![]);
+        };
+
+        let prefix = snapshot_prefix(&path)?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut snapshots = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(created_at) = name
+                .strip_prefix(&prefix)
+                .and_then(|ts| ts.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            snapshots.push(Snapshot {
+                name,
+                created_at,
+                size: entry.metadata()?.len(),
+            });
+        }
+        snapshots.sort_by_key(|snapshot| Reverse(snapshot.created_at));
+
+        Ok(snapshots)
+    }
+
+    async fn restore_snapshot(&self, name: String) -> anyhow::Result<()> {
+        let Some(path) = self.database_path() else {
+            bail!("in-memory database has no snapshots");
+        };
+
+        if !self
+            .list_snapshots()
+            .await?
+            .iter()
+            .any(|snapshot| snapshot.name == name)
+        {
+            bail!("snapshot not found: {}", name);
+        }
+        let snapshot_path = path.with_file_name(&name);
+
+        tracing::info!(snapshot = name, "restoring database snapshot");
+        self.pool().close().await;
+
+        tokio::task::spawn_blocking(move || -> io::Result<()> {
+            // copied next to the database and renamed over it, so a crash or
+            // a full disk can't leave a half-written database behind
+            let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+            tmp_name.push(".restore");
+            let tmp_path = path.with_file_name(tmp_name);
+            let copied = fs::copy(&snapshot_path, &tmp_path)
+                .and_then(|_| fs::File::open(&tmp_path)?.sync_all());
+            if let Err(e) = copied {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+
+            for file in database_files(&path).iter().skip(1) {
+                match fs::remove_file(file) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => (),
+                }
+            }
+            fs::rename(&tmp_path, &path)?;
+            #[cfg(unix)]
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::File::open(dir)?.sync_all()?;
+            }
+            Ok(())
+        })
+        .await??;
+
+        self.reopen_pool()
+    }
+}
+
+fn snapshot_prefix(path: &Path) -> anyhow::Result<String> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid database path: {}", path.display()))?;
+    Ok(format!("{}.snapshot-", file_name.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use crate::db::{
+        tokens::{TokenData, TokenOrder},
+        Database, Db,
+    };
+
+    #[tokio::test]
+    async fn test_snapshots() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.run_migration().await.unwrap();
+        // nothing worth keeping before the first migration
+        assert_eq!(db.list_snapshots().await.unwrap().len(), 0);
+
+        let id = db
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: "hoge".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let status = db.migration_status().await.unwrap();
+        db.rollback_to(status[1].version).await.unwrap();
+        db.run_migration().await.unwrap();
+
+        let snapshots = db.list_snapshots().await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0].name.starts_with("database.db.snapshot-"));
+        assert!(snapshots[0].size > 0);
+
+        db.remove_token(id).await.unwrap();
+        assert_eq!(db.list_tokens(TokenOrder::Created).await.unwrap().len(), 0);
+
+        db.restore_snapshot(snapshots[0].name.clone())
+            .await
+            .unwrap();
+        assert!(!temp_dir.path().join("database.db.restore").exists());
+        assert!(db.is_migration_available().await.unwrap());
+        db.run_migration().await.unwrap();
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, id);
+
+        assert!(db.restore_snapshot("../database.db".into()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_retention() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.run_migration().await.unwrap();
+
+        for _ in 0..5 {
+            db.create_snapshot().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        assert_eq!(db.list_snapshots().await.unwrap().len(), 3);
+    }
+}
@@ -1,3 +1,8 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
@@ -10,9 +15,13 @@ pub struct Token {
     pub id: u64,
     #[sqlx(flatten)]
     pub data: TokenData,
+    pub last_used_at: Option<u64>,
+    pub use_count: u64,
+    pub pinned: bool,
+    pub metadata_encrypted: bool,
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(sqlx::FromRow)]
 pub struct TokenData {
     pub account: String,
     pub service: Option<String>,
@@ -21,6 +30,25 @@ pub struct TokenData {
     pub algorithm: TokenAlg,
     pub digits: u8,
     pub period: u32,
+    /// Blind indexes of `account` and `service`. Set when those columns are
+    /// stored encrypted.
+    pub account_index: Option<String>,
+    pub service_index: Option<String>,
+}
+
+impl fmt::Debug for TokenData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenData")
+            .field("account", &"<redacted>")
+            .field("service", &self.service)
+            .field("secret", &"<redacted>")
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("account_index", &self.account_index)
+            .field("service_index", &self.service_index)
+            .finish()
+    }
 }
 
 impl Default for TokenData {
@@ -32,6 +60,8 @@ impl Default for TokenData {
             algorithm: TokenAlg::Sha1,
             digits: 6,
             period: 30,
+            account_index: None,
+            service_index: None,
         }
     }
 }
@@ -41,6 +71,8 @@ pub struct TokenListItem {
     pub id: u64,
     pub account: String,
     pub service: Option<String>,
+    pub pinned: bool,
+    pub metadata_encrypted: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -51,12 +83,36 @@ pub enum TokenAlg {
     Sha512,
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TokenOrder {
+    #[default]
+    Created,
+    RecentlyUsed,
+}
+
 #[async_trait]
 pub trait TokensDatabase {
     async fn add_token(&self, token: TokenData) -> Result<u64, Error>;
     async fn remove_token(&self, id: u64) -> Result<(), Error>;
-    async fn list_tokens(&self) -> Result<Vec<TokenListItem>, Error>;
+    async fn list_tokens(&self, order: TokenOrder) -> Result<Vec<TokenListItem>, Error>;
     async fn token_detail(&self, id: u64) -> Result<Option<Token>, Error>;
+    async fn record_token_use(&self, id: u64) -> Result<(), Error>;
+    async fn set_token_pinned(&self, id: u64, pinned: bool) -> Result<(), Error>;
+    async fn update_token_metadata(
+        &self,
+        id: u64,
+        account: String,
+        service: Option<String>,
+        account_index: String,
+        service_index: Option<String>,
+    ) -> Result<(), Error>;
+    /// Looks tokens up by blind index. `None` for `service_index` matches any
+    /// service.
+    async fn find_tokens(
+        &self,
+        account_index: String,
+        service_index: Option<String>,
+    ) -> Result<Vec<TokenListItem>, Error>;
 }
 
 #[async_trait]
@@ -64,7 +120,7 @@ impl TokensDatabase for Db {
     async fn add_token(&self, token: TokenData) -> Result<u64, Error> {
         let id = self.next_id().await?;
 
-        let _ = sqlx::query("INSERT INTO tokens (id, account, service, secret, algorithm, digits, period) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        let _ = sqlx::query("INSERT INTO tokens (id, account, service, secret, algorithm, digits, period, account_index, service_index, metadata_encrypted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
             .bind(id as i64)
             .bind(token.account)
             .bind(token.service)
@@ -72,7 +128,10 @@ impl TokensDatabase for Db {
             .bind(serde_json::to_string(&token.algorithm)?)
             .bind(token.digits)
             .bind(token.period)
-            .execute(&self.pool).await?;
+            .bind(&token.account_index)
+            .bind(token.service_index)
+            .bind(token.account_index.is_some())
+            .execute(&self.pool()).await?;
 
         Ok(id)
     }
@@ -80,26 +139,90 @@ impl TokensDatabase for Db {
     async fn remove_token(&self, id: u64) -> Result<(), Error> {
         let _ = sqlx::query("DELETE FROM tokens WHERE id = ?")
             .bind(id as i64)
-            .execute(&self.pool)
+            .execute(&self.pool())
             .await?;
         Ok(())
     }
 
-    async fn list_tokens(&self) -> Result<Vec<TokenListItem>, Error> {
-        let tokens: Vec<TokenListItem> =
-            sqlx::query_as("SELECT id, account, service FROM tokens ORDER BY id")
-                .fetch_all(&self.pool)
-                .await?;
+    async fn list_tokens(&self, order: TokenOrder) -> Result<Vec<TokenListItem>, Error> {
+        let query = match order {
+            TokenOrder::Created => {
+                "SELECT id, account, service, pinned, metadata_encrypted FROM tokens ORDER BY pinned DESC, id"
+            }
+            TokenOrder::RecentlyUsed => {
+                "SELECT id, account, service, pinned, metadata_encrypted FROM tokens ORDER BY pinned DESC, last_used_at IS NULL, last_used_at DESC, id"
+            }
+        };
+        let tokens: Vec<TokenListItem> = sqlx::query_as(query).fetch_all(&self.pool()).await?;
         Ok(tokens)
     }
 
     async fn token_detail(&self, id: u64) -> Result<Option<Token>, Error> {
         let token: Option<Token> = sqlx::query_as("SELECT * FROM tokens WHERE id = ?")
             .bind(id as i64)
-            .fetch_optional(&self.pool)
+            .fetch_optional(&self.pool())
             .await?;
         Ok(token)
     }
+
+    async fn record_token_use(&self, id: u64) -> Result<(), Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(anyhow::Error::from)?
+            .as_secs();
+
+        let _ = sqlx::query(
+            "UPDATE tokens SET last_used_at = ?, use_count = use_count + 1 WHERE id = ?",
+        )
+        .bind(now as i64)
+        .bind(id as i64)
+        .execute(&self.pool())
+        .await?;
+        Ok(())
+    }
+
+    async fn set_token_pinned(&self, id: u64, pinned: bool) -> Result<(), Error> {
+        let _ = sqlx::query("UPDATE tokens SET pinned = ? WHERE id = ?")
+            .bind(pinned)
+            .bind(id as i64)
+            .execute(&self.pool())
+            .await?;
+        Ok(())
+    }
+
+    async fn update_token_metadata(
+        &self,
+        id: u64,
+        account: String,
+        service: Option<String>,
+        account_index: String,
+        service_index: Option<String>,
+    ) -> Result<(), Error> {
+        let _ = sqlx::query("UPDATE tokens SET account = ?, service = ?, account_index = ?, service_index = ?, metadata_encrypted = TRUE WHERE id = ?")
+            .bind(account)
+            .bind(service)
+            .bind(account_index)
+            .bind(service_index)
+            .bind(id as i64)
+            .execute(&self.pool())
+            .await?;
+        Ok(())
+    }
+
+    async fn find_tokens(
+        &self,
+        account_index: String,
+        service_index: Option<String>,
+    ) -> Result<Vec<TokenListItem>, Error> {
+        let tokens: Vec<TokenListItem> = sqlx::query_as(
+            "SELECT id, account, service, pinned, metadata_encrypted FROM tokens WHERE account_index = ? AND (?2 IS NULL OR service_index = ?2) ORDER BY pinned DESC, id",
+        )
+        .bind(account_index)
+        .bind(service_index)
+        .fetch_all(&self.pool())
+        .await?;
+        Ok(tokens)
+    }
 }
 
 #[cfg(test)]
@@ -108,7 +231,7 @@ mod tests {
 
     use tempfile::tempdir;
 
-    use super::TokenData;
+    use super::{TokenData, TokenOrder};
     use crate::db::{Database, Db};
 
     #[tokio::test]
@@ -130,7 +253,7 @@ mod tests {
         assert!(res.is_ok());
         let id = res.unwrap();
 
-        let tokens = db.list_tokens().await.unwrap();
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0].id, id);
 
@@ -145,7 +268,154 @@ mod tests {
 
         let token = db.token_detail(id).await.unwrap();
         assert!(token.is_none());
-        let tokens = db.list_tokens().await.unwrap();
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
         assert_eq!(tokens.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_token_usage() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.reset_database().await.unwrap();
+        db.run_migration().await.unwrap();
+
+        let mut ids = vec![];
+        for account in ["a", "b", "c"] {
+            let id = db
+                .add_token(TokenData {
+                    account: account.into(),
+                    secret: "hoge".into(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+
+        let token = db.token_detail(ids[0]).await.unwrap().unwrap();
+        assert_eq!(token.use_count, 0);
+        assert!(token.last_used_at.is_none());
+
+        db.record_token_use(ids[1]).await.unwrap();
+        db.record_token_use(ids[1]).await.unwrap();
+
+        let token = db.token_detail(ids[1]).await.unwrap().unwrap();
+        assert_eq!(token.use_count, 2);
+        assert!(token.last_used_at.is_some());
+
+        let tokens = db.list_tokens(TokenOrder::RecentlyUsed).await.unwrap();
+        let order: Vec<u64> = tokens.iter().map(|t| t.id).collect();
+        assert_eq!(order, vec![ids[1], ids[0], ids[2]]);
+
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        let order: Vec<u64> = tokens.iter().map(|t| t.id).collect();
+        assert_eq!(order, ids);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_tokens() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        db.reset_database().await.unwrap();
+        db.run_migration().await.unwrap();
+
+        let mut ids = vec![];
+        for account in ["a", "b", "c", "d"] {
+            let id = db
+                .add_token(TokenData {
+                    account: account.into(),
+                    secret: "hoge".into(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+
+        db.set_token_pinned(ids[3], true).await.unwrap();
+        db.set_token_pinned(ids[1], true).await.unwrap();
+
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        let order: Vec<u64> = tokens.iter().map(|t| t.id).collect();
+        assert_eq!(order, vec![ids[1], ids[3], ids[0], ids[2]]);
+        assert!(tokens[0].pinned);
+        assert!(!tokens[2].pinned);
+
+        db.record_token_use(ids[2]).await.unwrap();
+        let tokens = db.list_tokens(TokenOrder::RecentlyUsed).await.unwrap();
+        let order: Vec<u64> = tokens.iter().map(|t| t.id).collect();
+        assert_eq!(order, vec![ids[1], ids[3], ids[2], ids[0]]);
+
+        db.set_token_pinned(ids[1], false).await.unwrap();
+        let token = db.token_detail(ids[1]).await.unwrap().unwrap();
+        assert!(!token.pinned);
+    }
+
+    #[tokio::test]
+    async fn test_token_metadata() {
+        let db: Arc<dyn Database> = Db::new("sqlite::memory:".into()).unwrap();
+        db.run_migration().await.unwrap();
+
+        let legacy = db
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: "hoge".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let id = db
+            .add_token(TokenData {
+                account: "encrypted-account".into(),
+                service: Some("encrypted-service".into()),
+                secret: "hoge".into(),
+                account_index: Some("account".into()),
+                service_index: Some("service".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let tokens = db.list_tokens(TokenOrder::Created).await.unwrap();
+        assert!(!tokens[0].metadata_encrypted);
+        assert!(tokens[1].metadata_encrypted);
+
+        let found = db.find_tokens("account".into(), None).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, id);
+        let found = db
+            .find_tokens("account".into(), Some("service".into()))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        let found = db
+            .find_tokens("account".into(), Some("other".into()))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 0);
+
+        db.update_token_metadata(legacy, "encrypted".into(), None, "legacy".into(), None)
+            .await
+            .unwrap();
+        let token = db.token_detail(legacy).await.unwrap().unwrap();
+        assert!(token.metadata_encrypted);
+        assert_eq!(token.data.account, "encrypted");
+        assert_eq!(token.data.account_index.as_deref(), Some("legacy"));
+    }
+
+    #[test]
+    fn test_token_data_debug() {
+        let data = TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        };
+        let debug = format!("{:?}", data);
+        assert!(!debug.contains("dameleon"));
+        assert!(!debug.contains("hoge"));
+    }
 }
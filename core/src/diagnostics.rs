@@ -0,0 +1,142 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{
+    db::{tokens::TokenOrder, Database},
+    logger::file::{read_logs, MAX_LOG_FILES},
+};
+
+/// Everything needed to look into a bug report, without secrets: logs are
+/// already redacted when written and tokens are only counted.
+#[derive(Debug, Serialize)]
+pub struct Diagnostics {
+    pub core_version: &'static str,
+    /// Unix time in milliseconds.
+    pub generated_at: u64,
+    /// Latest applied migration.
+    pub schema_version: Option<i64>,
+    pub pending_migrations: Vec<i64>,
+    pub mismatched_migrations: Vec<i64>,
+    pub tokens: Option<TokenCounts>,
+    /// Failures while collecting, so a broken database still yields a report.
+    pub errors: Vec<String>,
+    pub logs: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenCounts {
+    pub total: usize,
+    pub pinned: usize,
+    pub encrypted_metadata: usize,
+}
+
+pub async fn collect(db: &dyn Database, log_dir: Option<&Path>) -> Diagnostics {
+    let mut diagnostics = Diagnostics {
+        core_version: env!("CARGO_PKG_VERSION"),
+        generated_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        schema_version: None,
+        pending_migrations: vec![],
+        mismatched_migrations: vec![],
+        tokens: None,
+        errors: vec![],
+        logs: vec![],
+    };
+
+    match db.migration_status().await {
+        Ok(status) => {
+            for m in status {
+                if !m.applied {
+                    diagnostics.pending_migrations.push(m.version);
+                    continue;
+                }
+                diagnostics.schema_version = Some(m.version);
+                if m.mismatched {
+                    diagnostics.mismatched_migrations.push(m.version);
+                }
+            }
+        }
+        Err(e) => diagnostics.errors.push(format!("migration status: {}", e)),
+    }
+
+    match db.list_tokens(TokenOrder::Created).await {
+        Ok(tokens) => {
+            diagnostics.tokens = Some(TokenCounts {
+                total: tokens.len(),
+                pinned: tokens.iter().filter(|t| t.pinned).count(),
+                encrypted_metadata: tokens.iter().filter(|t| t.metadata_encrypted).count(),
+            })
+        }
+        Err(e) => diagnostics.errors.push(format!("tokens: {}", e)),
+    }
+
+    if let Some(log_dir) = log_dir {
+        match read_logs(log_dir, MAX_LOG_FILES) {
+            Ok(logs) => diagnostics.logs = logs,
+            Err(e) => diagnostics.errors.push(format!("logs: {}", e)),
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use tempfile::tempdir;
+
+    use super::collect;
+    use crate::{
+        db::{tokens::TokenData, Database, Db},
+        logger::{file::FileLogger, LogLevel, LogRecord, Logger},
+    };
+
+    #[tokio::test]
+    async fn test_collect() {
+        let temp_dir = tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+        let log_dir = temp_dir.path().join("logs");
+
+        let db: Arc<dyn Database> = Db::new(database_url).unwrap();
+        let diagnostics = collect(db.as_ref(), None).await;
+        assert_eq!(diagnostics.schema_version, None);
+        assert!(!diagnostics.pending_migrations.is_empty());
+
+        db.run_migration().await.unwrap();
+        db.add_token(TokenData {
+            account: "dameleon".into(),
+            secret: "hoge".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        FileLogger::new(&log_dir, 1024, 2).log(LogRecord {
+            level: LogLevel::Info,
+            timestamp: 0,
+            target: "auth2".into(),
+            module: None,
+            message: "hello".into(),
+            fields: HashMap::new(),
+            spans: vec![],
+        });
+
+        let diagnostics = collect(db.as_ref(), Some(&log_dir)).await;
+        assert!(diagnostics.schema_version.is_some());
+        assert!(diagnostics.pending_migrations.is_empty());
+        assert_eq!(diagnostics.tokens.as_ref().unwrap().total, 1);
+        assert_eq!(diagnostics.logs.len(), 1);
+        assert!(diagnostics.errors.is_empty());
+
+        let json = serde_json::to_string(&diagnostics).unwrap();
+        assert!(!json.contains("dameleon"));
+        assert!(!json.contains("hoge"));
+    }
+}
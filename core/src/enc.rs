@@ -1,8 +1,10 @@
 use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
 use anyhow::anyhow;
 use base64::Engine;
+use hmac::{Hmac, Mac};
 use rand::Rng;
 use sha2::Sha256;
+use zeroize::Zeroizing;
 
 use crate::error::Error;
 
@@ -12,16 +14,128 @@ fn generate_iv() -> [u8; 12] {
     iv
 }
 
-fn derive_aes_key(user_key: &[u8], salt: &[u8]) -> [u8; 32] {
-    let mut key = [0; 32];
-    pbkdf2::pbkdf2_hmac::<Sha256>(user_key, salt, 10_000, &mut key);
+/// PBKDF2 iterations for keys derived from the user key, and the default
+/// for key slots.
+pub const KEY_ITERATIONS: u32 = 10_000;
+
+fn derive_aes_key(user_key: &[u8], salt: &[u8]) -> Zeroizing<[u8; 32]> {
+    derive_key(user_key, salt, KEY_ITERATIONS)
+}
+
+fn derive_key(secret: &[u8], salt: &[u8], iterations: u32) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0; 32]);
+    pbkdf2::pbkdf2_hmac::<Sha256>(secret, salt, iterations, key.as_mut());
+    key
+}
+
+/// A new random vault data key.
+pub fn generate_data_key() -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0; 32]);
+    rand::thread_rng().fill(key.as_mut_slice());
+    key
+}
+
+/// Unambiguous characters for recovery keys, 5 bits each.
+const RECOVERY_KEY_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// A random 150-bit recovery key, written as six groups of five characters
+/// to make it easy to copy down.
+pub fn generate_recovery_key() -> Zeroizing<String> {
+    let mut rng = rand::thread_rng();
+    let mut key = Zeroizing::new(String::with_capacity(35));
+    for i in 0..30 {
+        if i > 0 && i % 5 == 0 {
+            key.push('-');
+        }
+        key.push(RECOVERY_KEY_ALPHABET[rng.gen_range(0..RECOVERY_KEY_ALPHABET.len())] as char);
+    }
     key
 }
 
-pub fn encrypt_secret(user_key: String, secret: String) -> anyhow::Result<String> {
+/// The recovery key as it's used for wrapping, so it can be typed in
+/// lowercase and without the dashes.
+pub fn normalize_recovery_key(key: &str) -> Zeroizing<String> {
+    Zeroizing::new(
+        key.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect(),
+    )
+}
+
+/// Encrypts `data_key` with a key derived from an unlock method's `secret`,
+/// in the same layout as `encrypt_secret`.
+pub fn wrap_key(secret: &[u8], iterations: u32, data_key: &[u8]) -> anyhow::Result<String> {
     let salt = generate_iv();
-    let encrypt_key = derive_aes_key(user_key.as_bytes(), &salt);
-    let encrypt_key = Key::<Aes256Gcm>::from_slice(&encrypt_key);
+    let wrap_key = derive_key(secret, &salt, iterations);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(wrap_key.as_ref()));
+
+    let iv = generate_iv();
+    let wrapped = cipher
+        .encrypt(Nonce::from_slice(&iv), data_key)
+        .map_err(|e| anyhow!(e))?;
+
+    let mut result = Vec::with_capacity(12 + 12 + wrapped.len());
+    result.extend_from_slice(&iv);
+    result.extend_from_slice(&salt);
+    result.extend_from_slice(&wrapped);
+
+    Ok(base64::engine::general_purpose::STANDARD.encode(&result))
+}
+
+/// Fails with `Error::DecryptError` if `secret` isn't the one the key was
+/// wrapped with.
+pub fn unwrap_key(
+    secret: &[u8],
+    iterations: u32,
+    wrapped: &str,
+) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let wrapped = base64::engine::general_purpose::STANDARD
+        .decode(wrapped)
+        .map_err(|e| anyhow!(e))?;
+    if wrapped.len() < 24 {
+        return Err(Error::DecryptError);
+    }
+
+    let iv = &wrapped[0..12];
+    let salt = &wrapped[12..24];
+    let wrap_key = derive_key(secret, salt, iterations);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(wrap_key.as_ref()));
+
+    cipher
+        .decrypt(Nonce::from_slice(iv), &wrapped[24..])
+        .map(Zeroizing::new)
+        .map_err(|_| Error::DecryptError)
+}
+
+/// Derives the raw SQLCipher key from the user key, formatted as the hex
+/// blob literal `PRAGMA key` expects.
+#[cfg(feature = "sqlcipher")]
+pub fn derive_database_key(user_key: &[u8]) -> Zeroizing<String> {
+    let key = derive_aes_key(user_key, b"auth2-database-key");
+    let hex: Zeroizing<String> = Zeroizing::new(key.iter().map(|b| format!("{:02x}", b)).collect());
+    Zeroizing::new(format!("\"x'{}'\"", hex.as_str()))
+}
+
+/// Derives the key used for blind indexes, kept separate from the keys that
+/// encrypt the data itself.
+pub fn derive_index_key(user_key: &[u8]) -> Zeroizing<[u8; 32]> {
+    derive_aes_key(user_key, b"auth2-blind-index")
+}
+
+/// Keyed hash of `value` that allows exact-match lookups on encrypted
+/// columns. Case and surrounding whitespace are ignored.
+pub fn blind_index(index_key: &[u8], value: &str) -> String {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(index_key).expect("HMAC accepts any key length");
+    mac.update(value.trim().to_lowercase().as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+pub fn encrypt_secret(user_key: &[u8], secret: &str) -> anyhow::Result<String> {
+    let salt = generate_iv();
+    let encrypt_key = derive_aes_key(user_key, &salt);
+    let encrypt_key = Key::<Aes256Gcm>::from_slice(encrypt_key.as_ref());
 
     let iv = generate_iv();
     let nonce = Nonce::from_slice(&iv);
@@ -39,17 +153,20 @@ pub fn encrypt_secret(user_key: String, secret: String) -> anyhow::Result<String
     Ok(base64::engine::general_purpose::STANDARD.encode(&result))
 }
 
-pub fn decrypt_secret(user_key: String, encrypted: String) -> Result<String, Error> {
+pub fn decrypt_secret(user_key: &[u8], encrypted: &str) -> Result<Zeroizing<String>, Error> {
     let encrypted = base64::engine::general_purpose::STANDARD
         .decode(encrypted)
         .map_err(|e| anyhow!(e))?;
+    if encrypted.len() < 24 {
+        return Err(Error::DecryptError);
+    }
 
     let iv = &encrypted[0..12];
     let salt = &encrypted[12..24];
     let encrypted = &encrypted[24..];
 
-    let decrypt_key = derive_aes_key(user_key.as_bytes(), salt);
-    let decrypt_key = Key::<Aes256Gcm>::from_slice(&decrypt_key);
+    let decrypt_key = derive_aes_key(user_key, salt);
+    let decrypt_key = Key::<Aes256Gcm>::from_slice(decrypt_key.as_ref());
 
     let nonce = Nonce::from_slice(iv);
     let cipher = Aes256Gcm::new(decrypt_key);
@@ -57,23 +174,38 @@ pub fn decrypt_secret(user_key: String, encrypted: String) -> Result<String, Err
     let decrypted = cipher
         .decrypt(nonce, encrypted)
         .map_err(|_| Error::DecryptError)?;
-    Ok(String::from_utf8(decrypted).map_err(anyhow::Error::from)?)
+    match String::from_utf8(decrypted) {
+        Ok(decrypted) => Ok(Zeroizing::new(decrypted)),
+        Err(e) => {
+            let err = anyhow!(e.utf8_error());
+            drop(Zeroizing::new(e.into_bytes()));
+            Err(err.into())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{enc::decrypt_secret, error::Error};
 
-    use super::encrypt_secret;
+    use super::{
+        blind_index, derive_index_key, encrypt_secret, generate_recovery_key,
+        normalize_recovery_key, unwrap_key, wrap_key,
+    };
 
     #[test]
     fn test_encrypt() {
-        let data = encrypt_secret("test".into(), "secret".into()).unwrap();
+        let data = encrypt_secret(b"test", "secret").unwrap();
         println!("encrypted: {}", data);
-        let res = decrypt_secret("test".into(), data.clone()).unwrap();
-        assert_eq!(res, "secret");
+        let res = decrypt_secret(b"test", &data).unwrap();
+        assert_eq!(res.as_str(), "secret");
 
-        let res = decrypt_secret("test?".into(), data.clone());
+        assert!(matches!(
+            decrypt_secret(b"test", ""),
+            Err(Error::DecryptError)
+        ));
+
+        let res = decrypt_secret(b"test?", &data);
         match res {
             Err(Error::DecryptError) => (),
             _ => {
@@ -81,4 +213,58 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_blind_index() {
+        let key = derive_index_key(b"test");
+        assert_eq!(
+            blind_index(key.as_ref(), "Foo@example.com"),
+            blind_index(key.as_ref(), " foo@example.com")
+        );
+        assert_ne!(
+            blind_index(key.as_ref(), "foo@example.com"),
+            blind_index(key.as_ref(), "bar@example.com")
+        );
+
+        let other_key = derive_index_key(b"test?");
+        assert_ne!(
+            blind_index(key.as_ref(), "foo"),
+            blind_index(other_key.as_ref(), "foo")
+        );
+    }
+
+    #[test]
+    fn test_recovery_key() {
+        let key = generate_recovery_key();
+        assert_eq!(key.len(), 35);
+        assert_eq!(key.split('-').count(), 6);
+        assert_ne!(*key, *generate_recovery_key());
+
+        let normalized = normalize_recovery_key(&key);
+        assert_eq!(normalized.len(), 30);
+        assert_eq!(
+            normalize_recovery_key(&format!(" {} ", key.to_lowercase().replace('-', " "))),
+            normalized
+        );
+    }
+
+    #[test]
+    fn test_wrap_key() {
+        let wrapped = wrap_key(b"passphrase", 1_000, b"data key").unwrap();
+        let key = unwrap_key(b"passphrase", 1_000, &wrapped).unwrap();
+        assert_eq!(key.as_slice(), b"data key");
+
+        assert!(matches!(
+            unwrap_key(b"wrong", 1_000, &wrapped),
+            Err(Error::DecryptError)
+        ));
+        assert!(matches!(
+            unwrap_key(b"passphrase", 1_001, &wrapped),
+            Err(Error::DecryptError)
+        ));
+        assert!(matches!(
+            unwrap_key(b"passphrase", 1_000, "AAAA"),
+            Err(Error::DecryptError)
+        ));
+    }
 }
@@ -1,3 +1,6 @@
+use crate::{config::KeyStoreError, db::migrate::MismatchedMigrations};
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, uniffi::Error, thiserror::Error)]
 pub enum Error {
     #[error("internal error: {0}")]
@@ -6,8 +9,14 @@ pub enum Error {
     #[error("database migrate error: {0}")]
     MigrationError(String),
 
+    #[error("mismatched database migrations: {versions:?}")]
+    MigrationMismatchError { versions: Vec<i64> },
+
     #[error("data decryption error")]
     DecryptError,
+
+    #[error("key store error: {0}")]
+    KeyStoreError(#[from] KeyStoreError),
 }
 
 impl From<uniffi::UnexpectedUniFFICallbackError> for Error {
@@ -24,6 +33,11 @@ impl From<tokio::task::JoinError> for Error {
 
 impl From<anyhow::Error> for Error {
     fn from(value: anyhow::Error) -> Self {
+        if let Some(e) = value.downcast_ref::<MismatchedMigrations>() {
+            return Self::MigrationMismatchError {
+                versions: e.versions.clone(),
+            };
+        }
         Self::InternalError(value.to_string())
     }
 }
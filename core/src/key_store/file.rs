@@ -0,0 +1,277 @@
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::config::{KeyStore, KeyStoreError};
+
+const KEY_FILE_VERSION: u32 = 1;
+
+/// Argon2id cost parameters for wrapping the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory in KiB.
+    pub m_cost: u32,
+    /// Iterations.
+    pub t_cost: u32,
+    /// Parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// The second recommended option of RFC 9106: 64 MiB, 3 iterations.
+    fn default() -> Self {
+        Self {
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    version: u32,
+    kdf: KdfParams,
+    salt: String,
+    nonce: String,
+    key: String,
+}
+
+/// Keeps a key in a file, encrypted with a passphrase from another key
+/// store through Argon2id. The decrypted key is kept in memory once read.
+pub struct KeyFileStore {
+    path: PathBuf,
+    passphrase: Arc<dyn KeyStore>,
+    params: KdfParams,
+    key: Mutex<Option<Zeroizing<Vec<u8>>>>,
+}
+
+impl KeyFileStore {
+    pub fn new(path: impl Into<PathBuf>, passphrase: Arc<dyn KeyStore>) -> Self {
+        Self {
+            path: path.into(),
+            passphrase,
+            params: KdfParams::default(),
+            key: Mutex::new(None),
+        }
+    }
+
+    /// Costs used when writing the file. Reading uses the ones stored in it.
+    pub fn with_params(mut self, params: KdfParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Stores a new random 256-bit key.
+    pub fn generate(&self) -> Result<(), KeyStoreError> {
+        let mut key = Zeroizing::new(vec![0; 32]);
+        rand::thread_rng().fill(key.as_mut_slice());
+        self.set(key.to_vec())
+    }
+
+    fn passphrase(&self) -> Result<Zeroizing<Vec<u8>>, KeyStoreError> {
+        match self.passphrase.get()? {
+            Some(passphrase) => Ok(Zeroizing::new(passphrase)),
+            None => Err(KeyStoreError::Failed(
+                "no passphrase for the key file".into(),
+            )),
+        }
+    }
+
+    fn read(&self) -> Result<Option<Zeroizing<Vec<u8>>>, KeyStoreError> {
+        let json = match fs::read(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(failed("failed to read key file", e)),
+        };
+        let file: KeyFile =
+            serde_json::from_slice(&json).map_err(|e| failed("invalid key file", e))?;
+        if file.version != KEY_FILE_VERSION {
+            return Err(KeyStoreError::Failed(format!(
+                "unsupported key file version {}",
+                file.version
+            )));
+        }
+        let decode = |value: &str| {
+            STANDARD
+                .decode(value)
+                .map_err(|e| failed("invalid key file", e))
+        };
+        let salt = decode(&file.salt)?;
+        let nonce = decode(&file.nonce)?;
+        let key = decode(&file.key)?;
+        if nonce.len() != 12 {
+            return Err(KeyStoreError::Failed("invalid key file".into()));
+        }
+
+        let kek = derive_kek(&self.passphrase()?, &salt, file.kdf)?;
+        let key = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(kek.as_ref()))
+            .decrypt(Nonce::from_slice(&nonce), key.as_slice())
+            .map_err(|_| KeyStoreError::Failed("wrong passphrase for the key file".into()))?;
+        Ok(Some(Zeroizing::new(key)))
+    }
+
+    fn write(&self, key: &[u8]) -> Result<(), KeyStoreError> {
+        let mut salt = [0; 16];
+        rand::thread_rng().fill(&mut salt);
+        let mut nonce = [0; 12];
+        rand::thread_rng().fill(&mut nonce);
+
+        let kek = derive_kek(&self.passphrase()?, &salt, self.params)?;
+        let encrypted = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(kek.as_ref()))
+            .encrypt(Nonce::from_slice(&nonce), key)
+            .map_err(|e| failed("failed to encrypt key", e))?;
+        let file = KeyFile {
+            version: KEY_FILE_VERSION,
+            kdf: self.params,
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            key: STANDARD.encode(encrypted),
+        };
+        let json = serde_json::to_vec_pretty(&file).map_err(|e| failed("invalid key file", e))?;
+
+        // written next to the file and renamed over it, so a crash can't
+        // leave a half-written key behind
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| failed("failed to create key file", e))?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&tmp)
+            .and_then(|mut f| f.write_all(&json).and_then(|_| f.sync_all()))
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| failed("failed to write key file", e))
+    }
+}
+
+fn derive_kek(
+    passphrase: &[u8],
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, KeyStoreError> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| failed("invalid key file parameters", e))?;
+    let mut kek = Zeroizing::new([0; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, kek.as_mut())
+        .map_err(|e| failed("failed to derive key file key", e))?;
+    Ok(kek)
+}
+
+fn failed(context: &str, e: impl fmt::Display) -> KeyStoreError {
+    KeyStoreError::Failed(format!("{}: {}", context, e))
+}
+
+impl fmt::Debug for KeyFileStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyFileStore")
+            .field("path", &self.path)
+            .field("passphrase", &self.passphrase)
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyStore for KeyFileStore {
+    fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+        let mut key = self.key.lock().unwrap();
+        if key.is_none() {
+            *key = self.read()?;
+        }
+        Ok(key.as_ref().map(|k| k.to_vec()))
+    }
+
+    fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError> {
+        let key = Zeroizing::new(key);
+        self.write(&key)?;
+        *self.key.lock().unwrap() = Some(key);
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        *self.key.lock().unwrap() = None;
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(failed("failed to delete key file", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::tempdir;
+
+    use super::{KdfParams, KeyFileStore};
+    use crate::{config::KeyStore, key_store::MemoryKeyStore};
+
+    const PARAMS: KdfParams = KdfParams {
+        m_cost: 256,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn key_file_store(path: &std::path::Path, passphrase: &str) -> KeyFileStore {
+        KeyFileStore::new(path, Arc::new(MemoryKeyStore::new(passphrase))).with_params(PARAMS)
+    }
+
+    #[test]
+    fn test_key_file_store() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("auth2").join("key.json");
+
+        let key_store = key_file_store(&path, "passphrase");
+        assert!(!key_store.exists());
+        assert_eq!(key_store.get().unwrap(), None);
+
+        key_store.generate().unwrap();
+        let key = key_store.get().unwrap().unwrap();
+        assert_eq!(key.len(), 32);
+
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"m_cost\": 256"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // a fresh store reads it back from disk
+        let reopened = KeyFileStore::new(&path, Arc::new(MemoryKeyStore::new("passphrase")));
+        assert_eq!(reopened.get().unwrap(), Some(key));
+
+        assert!(key_file_store(&path, "wrong").get().is_err());
+        assert!(
+            KeyFileStore::new(&path, Arc::new(MemoryKeyStore::default()))
+                .get()
+                .is_err()
+        );
+
+        key_store.delete().unwrap();
+        assert!(!path.exists());
+        assert_eq!(key_store.get().unwrap(), None);
+        key_store.delete().unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+//! Ready-made `KeyStore`s for Rust consumers, e.g. desktop binaries and
+//! tests. Platform apps implement their own over FFI.
+
+use std::{env, fmt, sync::Mutex};
+
+use zeroize::Zeroizing;
+
+use crate::config::{KeyStore, KeyStoreError};
+
+#[cfg(feature = "key-file")]
+mod file;
+#[cfg(feature = "passphrase-prompt")]
+mod passphrase;
+#[cfg(feature = "secret-service")]
+mod secret_service;
+
+#[cfg(feature = "key-file")]
+pub use file::{KdfParams, KeyFileStore};
+#[cfg(feature = "passphrase-prompt")]
+pub use passphrase::{read_secret, PassphraseKeyStore};
+#[cfg(feature = "secret-service")]
+pub use secret_service::SecretServiceKeyStore;
+
+/// Environment variable read by `EnvKeyStore::default()`.
+pub const PASSPHRASE_ENV: &str = "AUTH2_PASSPHRASE";
+
+/// Holds the key in memory only, for tests and for keys obtained some
+/// other way.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    key: Mutex<Option<Zeroizing<Vec<u8>>>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: Mutex::new(Some(Zeroizing::new(key.into()))),
+        }
+    }
+}
+
+impl fmt::Debug for MemoryKeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryKeyStore").finish_non_exhaustive()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+        Ok(self.key.lock().unwrap().as_ref().map(|key| key.to_vec()))
+    }
+
+    fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError> {
+        *self.key.lock().unwrap() = Some(Zeroizing::new(key));
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        *self.key.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// Reads the key from an environment variable, `AUTH2_PASSPHRASE` by
+/// default. An empty variable counts as unset.
+#[derive(Debug)]
+pub struct EnvKeyStore {
+    var: String,
+}
+
+impl EnvKeyStore {
+    pub fn new(var: impl Into<String>) -> Self {
+        Self { var: var.into() }
+    }
+
+    /// Whether the variable is set, to decide between this and an
+    /// interactive store up front.
+    pub fn is_set(&self) -> bool {
+        env::var_os(&self.var).is_some_and(|v| !v.is_empty())
+    }
+}
+
+impl Default for EnvKeyStore {
+    fn default() -> Self {
+        Self::new(PASSPHRASE_ENV)
+    }
+}
+
+impl KeyStore for EnvKeyStore {
+    fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+        match env::var(&self.var) {
+            Ok(key) if key.is_empty() => Ok(None),
+            Ok(key) => Ok(Some(key.into_bytes())),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(_)) => Err(KeyStoreError::Failed(format!(
+                "{} is not valid UTF-8",
+                self.var
+            ))),
+        }
+    }
+
+    fn set(&self, _key: Vec<u8>) -> Result<(), KeyStoreError> {
+        Err(KeyStoreError::Unsupported)
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        Err(KeyStoreError::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::{EnvKeyStore, MemoryKeyStore};
+    use crate::config::{KeyStore, KeyStoreError};
+
+    #[test]
+    fn test_memory_key_store() {
+        let key_store = MemoryKeyStore::default();
+        assert_eq!(key_store.get().unwrap(), None);
+
+        key_store.set(vec![0xff, 0x00]).unwrap();
+        assert_eq!(key_store.get().unwrap(), Some(vec![0xff, 0x00]));
+        assert!(!format!("{:?}", key_store).contains("255"));
+
+        key_store.delete().unwrap();
+        assert_eq!(key_store.get().unwrap(), None);
+
+        let key_store = MemoryKeyStore::new("test");
+        assert_eq!(key_store.get().unwrap(), Some(b"test".to_vec()));
+    }
+
+    #[test]
+    fn test_env_key_store() {
+        let var = "AUTH2_TEST_ENV_KEY_STORE";
+        let key_store = EnvKeyStore::new(var);
+
+        env::remove_var(var);
+        assert!(!key_store.is_set());
+        assert_eq!(key_store.get().unwrap(), None);
+
+        env::set_var(var, "");
+        assert!(!key_store.is_set());
+        assert_eq!(key_store.get().unwrap(), None);
+
+        env::set_var(var, "passphrase");
+        assert!(key_store.is_set());
+        assert_eq!(key_store.get().unwrap(), Some(b"passphrase".to_vec()));
+        assert!(matches!(
+            key_store.set(b"other".to_vec()),
+            Err(KeyStoreError::Unsupported)
+        ));
+
+        env::remove_var(var);
+    }
+}
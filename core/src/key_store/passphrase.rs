@@ -0,0 +1,84 @@
+use std::{
+    fmt,
+    io::{self, BufRead, IsTerminal},
+    sync::Mutex,
+};
+
+use zeroize::Zeroizing;
+
+use crate::config::{KeyStore, KeyStoreError};
+
+/// Asks for a passphrase on the terminal the first time the key is needed,
+/// and keeps it for the life of the process.
+pub struct PassphraseKeyStore {
+    passphrase: Mutex<Option<Zeroizing<Vec<u8>>>>,
+    /// Ask twice, for setting a new passphrase.
+    confirm: bool,
+}
+
+impl PassphraseKeyStore {
+    pub fn new(confirm: bool) -> Self {
+        Self {
+            passphrase: Mutex::new(None),
+            confirm,
+        }
+    }
+
+    fn read(&self) -> io::Result<Option<Zeroizing<String>>> {
+        let passphrase = read_secret("Passphrase: ")?;
+        if passphrase.is_empty() {
+            eprintln!("passphrase must not be empty");
+            return Ok(None);
+        }
+        if self.confirm && *read_secret("Confirm passphrase: ")? != *passphrase {
+            eprintln!("passphrases don't match");
+            return Ok(None);
+        }
+        Ok(Some(passphrase))
+    }
+}
+
+impl fmt::Debug for PassphraseKeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PassphraseKeyStore")
+            .field("confirm", &self.confirm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyStore for PassphraseKeyStore {
+    fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+        let mut passphrase = self.passphrase.lock().unwrap();
+        if passphrase.is_none() {
+            *passphrase = self
+                .read()
+                .map_err(|e| KeyStoreError::Failed(format!("failed to read passphrase: {}", e)))?
+                .map(|p| Zeroizing::new(p.as_bytes().to_vec()));
+        }
+        Ok(passphrase.as_ref().map(|p| p.to_vec()))
+    }
+
+    fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError> {
+        *self.passphrase.lock().unwrap() = Some(Zeroizing::new(key));
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        *self.passphrase.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// Reads a line without echoing it, or from stdin when it isn't a terminal
+/// so secrets can be piped in.
+pub fn read_secret(prompt: &str) -> io::Result<Zeroizing<String>> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt).map(Zeroizing::new);
+    }
+
+    let mut line = Zeroizing::new(String::new());
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(Zeroizing::new(
+        line.trim_end_matches(['\r', '\n']).to_string(),
+    ))
+}
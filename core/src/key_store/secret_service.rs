@@ -0,0 +1,675 @@
+use std::{collections::HashMap, fmt, slice};
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use zbus::{
+    connection, proxy,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Type, Value},
+    Connection,
+};
+use zeroize::Zeroize;
+
+use crate::config::{AsyncKeyStore, KeyStoreError};
+
+const ITEM_LABEL: &str = "org.freedesktop.Secret.Item.Label";
+const ITEM_ATTRIBUTES: &str = "org.freedesktop.Secret.Item.Attributes";
+const COLLECTION_LABEL: &str = "org.freedesktop.Secret.Collection.Label";
+
+/// Object path the API uses for "none", e.g. when no prompt is needed.
+const NO_OBJECT: &str = "/";
+
+#[proxy(
+    interface = "org.freedesktop.Secret.Service",
+    default_service = "org.freedesktop.secrets",
+    default_path = "/org/freedesktop/secrets"
+)]
+trait Service {
+    fn open_session(
+        &self,
+        algorithm: &str,
+        input: &Value<'_>,
+    ) -> zbus::Result<(OwnedValue, OwnedObjectPath)>;
+
+    fn search_items(
+        &self,
+        attributes: HashMap<&str, &str>,
+    ) -> zbus::Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>)>;
+
+    fn unlock(
+        &self,
+        objects: &[&ObjectPath<'_>],
+    ) -> zbus::Result<(Vec<OwnedObjectPath>, OwnedObjectPath)>;
+
+    fn read_alias(&self, name: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn create_collection(
+        &self,
+        properties: HashMap<&str, Value<'_>>,
+        alias: &str,
+    ) -> zbus::Result<(OwnedObjectPath, OwnedObjectPath)>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.Secret.Collection",
+    default_service = "org.freedesktop.secrets"
+)]
+trait Collection {
+    fn create_item(
+        &self,
+        properties: HashMap<&str, Value<'_>>,
+        secret: &Secret,
+        replace: bool,
+    ) -> zbus::Result<(OwnedObjectPath, OwnedObjectPath)>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.Secret.Item",
+    default_service = "org.freedesktop.secrets"
+)]
+trait Item {
+    fn get_secret(&self, session: &ObjectPath<'_>) -> zbus::Result<Secret>;
+
+    fn delete(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn attributes(&self) -> zbus::Result<HashMap<String, String>>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.Secret.Prompt",
+    default_service = "org.freedesktop.secrets"
+)]
+trait Prompt {
+    fn prompt(&self, window_id: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn completed(&self, dismissed: bool, result: Value<'_>) -> zbus::Result<()>;
+}
+
+/// A secret as it goes over the bus, the `(oayays)` struct of the API.
+#[derive(Serialize, Deserialize, Type)]
+struct Secret {
+    session: OwnedObjectPath,
+    parameters: Vec<u8>,
+    value: Vec<u8>,
+    content_type: String,
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+struct Session {
+    connection: Connection,
+    path: OwnedObjectPath,
+}
+
+/// Keeps the key in the desktop's keyring, GNOME Keyring or KWallet,
+/// through the freedesktop Secret Service D-Bus API.
+///
+/// The key is looked up by attributes, `application=auth2` plus any added
+/// with `with_attribute`, and stored in the default collection. Unlocking
+/// it may show a prompt, and dismissing that yields
+/// `KeyStoreError::Cancelled`.
+///
+/// Secrets are transferred with the `plain` algorithm, so they are as
+/// private as the session bus, which only the user can connect to.
+pub struct SecretServiceKeyStore {
+    attributes: HashMap<String, String>,
+    label: String,
+    address: Option<String>,
+    session: OnceCell<Session>,
+}
+
+impl SecretServiceKeyStore {
+    pub fn new() -> Self {
+        Self {
+            attributes: HashMap::from([("application".into(), "auth2".into())]),
+            label: "auth2 vault key".into(),
+            address: None,
+            session: OnceCell::new(),
+        }
+    }
+
+    /// Adds an attribute to tell the keys of several vaults apart.
+    pub fn with_attribute(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.insert(name.into(), value.into());
+        self
+    }
+
+    /// Label shown for the key in keyring managers.
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    /// Connects to the bus at `address` instead of the session bus.
+    pub fn with_bus_address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    async fn session(&self) -> Result<&Session, KeyStoreError> {
+        self.session
+            .get_or_try_init(|| async {
+                let connection = match &self.address {
+                    Some(address) => {
+                        connection::Builder::address(address.as_str())?
+                            .build()
+                            .await?
+                    }
+                    None => Connection::session().await?,
+                };
+                let (_, path) = ServiceProxy::new(&connection)
+                    .await?
+                    .open_session("plain", &Value::from(""))
+                    .await?;
+                Ok(Session { connection, path })
+            })
+            .await
+    }
+
+    fn search_attributes(&self) -> HashMap<&str, &str> {
+        self.attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    /// All items with exactly our attributes, unlocked. Searching also
+    /// finds items with more attributes, such as other vaults' keys.
+    async fn items(&self, session: &Session) -> Result<Vec<OwnedObjectPath>, KeyStoreError> {
+        let service = ServiceProxy::new(&session.connection).await?;
+        let (mut found, locked) = service.search_items(self.search_attributes()).await?;
+        if !locked.is_empty() {
+            unlock(session, &locked).await?;
+            found.extend(locked);
+        }
+
+        let mut items = Vec::with_capacity(found.len());
+        for item in found {
+            let attributes = ItemProxy::builder(&session.connection)
+                .path(&item)?
+                .cache_properties(proxy::CacheProperties::No)
+                .build()
+                .await?
+                .attributes()
+                .await?;
+            if attributes == self.attributes {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
+    async fn default_collection(
+        &self,
+        session: &Session,
+    ) -> Result<OwnedObjectPath, KeyStoreError> {
+        let service = ServiceProxy::new(&session.connection).await?;
+        let collection = service.read_alias("default").await?;
+        if collection.as_str() != NO_OBJECT {
+            return Ok(collection);
+        }
+
+        // a fresh keyring may not have one yet
+        let properties = HashMap::from([(COLLECTION_LABEL, Value::from("Default"))]);
+        let (collection, prompt) = service.create_collection(properties, "default").await?;
+        if collection.as_str() != NO_OBJECT {
+            return Ok(collection);
+        }
+        match complete(session, prompt).await? {
+            Some(result) => Ok(OwnedObjectPath::try_from(result).map_err(zbus::Error::from)?),
+            None => Err(KeyStoreError::Failed(
+                "secret service created no collection".into(),
+            )),
+        }
+    }
+}
+
+impl Default for SecretServiceKeyStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SecretServiceKeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretServiceKeyStore")
+            .field("attributes", &self.attributes)
+            .field("label", &self.label)
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl AsyncKeyStore for SecretServiceKeyStore {
+    async fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+        let session = self.session().await?;
+        let Some(item) = self.items(session).await?.into_iter().next() else {
+            return Ok(None);
+        };
+        let secret = ItemProxy::builder(&session.connection)
+            .path(item)?
+            .build()
+            .await?
+            .get_secret(&session.path)
+            .await?;
+        Ok(Some(secret.value.clone()))
+    }
+
+    async fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError> {
+        let session = self.session().await?;
+        let collection = self.default_collection(session).await?;
+        unlock(session, slice::from_ref(&collection)).await?;
+
+        let properties = HashMap::from([
+            (ITEM_LABEL, Value::from(self.label.as_str())),
+            (ITEM_ATTRIBUTES, Value::from(self.search_attributes())),
+        ]);
+        let secret = Secret {
+            session: session.path.clone(),
+            parameters: Vec::new(),
+            value: key,
+            content_type: "application/octet-stream".into(),
+        };
+        let (_, prompt) = CollectionProxy::builder(&session.connection)
+            .path(collection)?
+            .build()
+            .await?
+            .create_item(properties, &secret, true)
+            .await?;
+        complete(session, prompt).await?;
+        Ok(())
+    }
+
+    async fn delete(&self) -> Result<(), KeyStoreError> {
+        let session = self.session().await?;
+        for item in self.items(session).await? {
+            let prompt = ItemProxy::builder(&session.connection)
+                .path(item)?
+                .build()
+                .await?
+                .delete()
+                .await?;
+            complete(session, prompt).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn unlock(session: &Session, objects: &[OwnedObjectPath]) -> Result<(), KeyStoreError> {
+    let objects: Vec<_> = objects.iter().map(|o| o.as_ref()).collect();
+    let objects: Vec<_> = objects.iter().collect();
+    let (_, prompt) = ServiceProxy::new(&session.connection)
+        .await?
+        .unlock(&objects)
+        .await?;
+    complete(session, prompt).await?;
+    Ok(())
+}
+
+/// Shows the prompt at `prompt`, if any, and waits for the user. Returns
+/// its result.
+async fn complete(
+    session: &Session,
+    prompt: OwnedObjectPath,
+) -> Result<Option<OwnedValue>, KeyStoreError> {
+    if prompt.as_str() == NO_OBJECT {
+        return Ok(None);
+    }
+
+    let prompt = PromptProxy::builder(&session.connection)
+        .path(prompt)?
+        .build()
+        .await?;
+    // subscribe first, the signal may come before the reply
+    let mut completed = prompt.receive_completed().await?;
+    prompt.prompt("").await?;
+    let Some(signal) = completed.next().await else {
+        return Err(KeyStoreError::Failed(
+            "secret service prompt went away".into(),
+        ));
+    };
+    let args = signal.args()?;
+    if args.dismissed {
+        return Err(KeyStoreError::Cancelled);
+    }
+    Ok(Some(args.result.try_to_owned().map_err(zbus::Error::from)?))
+}
+
+impl From<zbus::Error> for KeyStoreError {
+    fn from(value: zbus::Error) -> Self {
+        Self::Failed(format!("secret service: {}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::{Arc, Mutex},
+    };
+
+    use zbus::{
+        connection, fdo, interface,
+        zvariant::{OwnedObjectPath, OwnedValue, Value},
+        ObjectServer, SignalContext,
+    };
+
+    use super::{Secret, SecretServiceKeyStore, ITEM_ATTRIBUTES, NO_OBJECT};
+    use crate::config::{AsyncKeyStore, KeyStoreError};
+
+    const COLLECTION: &str = "/org/freedesktop/secrets/collection/login";
+
+    /// A private session bus, stopped on drop.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().into(),
+            })
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        locked: bool,
+        /// Whether the user dismisses prompts.
+        dismiss: bool,
+        items: BTreeMap<u32, (HashMap<String, String>, Vec<u8>)>,
+        next_id: u32,
+    }
+
+    type Shared = Arc<Mutex<State>>;
+
+    fn path(path: String) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(path).unwrap()
+    }
+
+    fn item_path(id: u32) -> OwnedObjectPath {
+        path(format!("{}/{}", COLLECTION, id))
+    }
+
+    fn locked_error() -> fdo::Error {
+        fdo::Error::AccessDenied("locked".into())
+    }
+
+    /// Just enough of a secrets provider for the store, with a single
+    /// collection that is unlocked through a prompt.
+    struct MockService(Shared);
+
+    #[interface(name = "org.freedesktop.Secret.Service")]
+    impl MockService {
+        fn open_session(
+            &self,
+            algorithm: &str,
+            _input: Value<'_>,
+        ) -> fdo::Result<(OwnedValue, OwnedObjectPath)> {
+            if algorithm != "plain" {
+                return Err(fdo::Error::NotSupported(algorithm.into()));
+            }
+            Ok((
+                OwnedValue::try_from(Value::from("")).unwrap(),
+                path("/org/freedesktop/secrets/session/1".into()),
+            ))
+        }
+
+        fn search_items(
+            &self,
+            attributes: HashMap<String, String>,
+        ) -> (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) {
+            let state = self.0.lock().unwrap();
+            let items = state
+                .items
+                .iter()
+                .filter(|(_, (item, _))| attributes.iter().all(|(k, v)| item.get(k) == Some(v)))
+                .map(|(id, _)| item_path(*id))
+                .collect();
+            if state.locked {
+                (Vec::new(), items)
+            } else {
+                (items, Vec::new())
+            }
+        }
+
+        async fn unlock(
+            &self,
+            objects: Vec<OwnedObjectPath>,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<(Vec<OwnedObjectPath>, OwnedObjectPath)> {
+            if !self.0.lock().unwrap().locked {
+                return Ok((objects, path(NO_OBJECT.into())));
+            }
+            let prompt = path("/org/freedesktop/secrets/prompt/unlock".into());
+            server.at(&prompt, MockPrompt(self.0.clone())).await?;
+            Ok((Vec::new(), prompt))
+        }
+
+        fn read_alias(&self, name: &str) -> OwnedObjectPath {
+            path(
+                if name == "default" {
+                    COLLECTION
+                } else {
+                    NO_OBJECT
+                }
+                .into(),
+            )
+        }
+    }
+
+    struct MockPrompt(Shared);
+
+    #[interface(name = "org.freedesktop.Secret.Prompt")]
+    impl MockPrompt {
+        async fn prompt(
+            &self,
+            _window_id: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<()> {
+            let dismissed = {
+                let mut state = self.0.lock().unwrap();
+                state.locked = state.dismiss;
+                state.dismiss
+            };
+            Self::completed(&ctxt, dismissed, Value::from("")).await?;
+            Ok(())
+        }
+
+        #[zbus(signal)]
+        async fn completed(
+            ctxt: &SignalContext<'_>,
+            dismissed: bool,
+            result: Value<'_>,
+        ) -> zbus::Result<()>;
+    }
+
+    struct MockCollection(Shared);
+
+    #[interface(name = "org.freedesktop.Secret.Collection")]
+    impl MockCollection {
+        async fn create_item(
+            &self,
+            properties: HashMap<String, OwnedValue>,
+            secret: Secret,
+            replace: bool,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<(OwnedObjectPath, OwnedObjectPath)> {
+            let attributes = properties
+                .get(ITEM_ATTRIBUTES)
+                .ok_or_else(|| fdo::Error::InvalidArgs("no attributes".into()))?;
+            let attributes = HashMap::<String, String>::try_from(attributes.try_clone().unwrap())
+                .map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+
+            let id = {
+                let mut state = self.0.lock().unwrap();
+                if state.locked {
+                    return Err(locked_error());
+                }
+                let existing = state
+                    .items
+                    .iter()
+                    .find(|(_, (item, _))| replace && *item == attributes)
+                    .map(|(id, _)| *id);
+                let id = existing.unwrap_or_else(|| {
+                    state.next_id += 1;
+                    state.next_id
+                });
+                state.items.insert(id, (attributes, secret.value.clone()));
+                id
+            };
+            server
+                .at(item_path(id), MockItem(self.0.clone(), id))
+                .await?;
+            Ok((item_path(id), path(NO_OBJECT.into())))
+        }
+    }
+
+    struct MockItem(Shared, u32);
+
+    #[interface(name = "org.freedesktop.Secret.Item")]
+    impl MockItem {
+        fn get_secret(&self, session: OwnedObjectPath) -> fdo::Result<Secret> {
+            let state = self.0.lock().unwrap();
+            if state.locked {
+                return Err(locked_error());
+            }
+            let (_, value) = state
+                .items
+                .get(&self.1)
+                .ok_or_else(|| fdo::Error::UnknownObject("deleted".into()))?;
+            Ok(Secret {
+                session,
+                parameters: Vec::new(),
+                value: value.clone(),
+                content_type: "application/octet-stream".into(),
+            })
+        }
+
+        fn delete(&self) -> fdo::Result<OwnedObjectPath> {
+            let mut state = self.0.lock().unwrap();
+            if state.locked {
+                return Err(locked_error());
+            }
+            state.items.remove(&self.1);
+            Ok(path(NO_OBJECT.into()))
+        }
+
+        #[zbus(property)]
+        fn attributes(&self) -> fdo::Result<HashMap<String, String>> {
+            let state = self.0.lock().unwrap();
+            let (attributes, _) = state
+                .items
+                .get(&self.1)
+                .ok_or_else(|| fdo::Error::UnknownObject("deleted".into()))?;
+            Ok(attributes.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_secret_service_key_store() {
+        let Some(bus) = Bus::start() else {
+            eprintln!("dbus-daemon not available, skipping");
+            return;
+        };
+        let key_store = SecretServiceKeyStore::new().with_bus_address(&bus.address);
+
+        // no provider on the bus yet
+        assert!(matches!(
+            SecretServiceKeyStore::new()
+                .with_bus_address(&bus.address)
+                .get()
+                .await,
+            Err(KeyStoreError::Failed(_))
+        ));
+
+        let state = Shared::default();
+        state.lock().unwrap().locked = true;
+        let _provider = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.freedesktop.secrets")
+            .unwrap()
+            .serve_at("/org/freedesktop/secrets", MockService(state.clone()))
+            .unwrap()
+            .serve_at(COLLECTION, MockCollection(state.clone()))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(key_store.get().await.unwrap(), None);
+
+        // storing unlocks the collection through a prompt
+        key_store.set(vec![0xff, 0x00]).await.unwrap();
+        assert!(!state.lock().unwrap().locked);
+        assert_eq!(key_store.get().await.unwrap(), Some(vec![0xff, 0x00]));
+
+        key_store.set(b"other".to_vec()).await.unwrap();
+        assert_eq!(state.lock().unwrap().items.len(), 1);
+        assert_eq!(key_store.get().await.unwrap(), Some(b"other".to_vec()));
+
+        // other attributes, other key, even though searching for ours
+        // finds it too
+        let other = SecretServiceKeyStore::new()
+            .with_attribute("vault", "work")
+            .with_bus_address(&bus.address);
+        assert_eq!(other.get().await.unwrap(), None);
+        other.set(b"work".to_vec()).await.unwrap();
+        assert_eq!(state.lock().unwrap().items.len(), 2);
+        assert_eq!(other.get().await.unwrap(), Some(b"work".to_vec()));
+        assert_eq!(key_store.get().await.unwrap(), Some(b"other".to_vec()));
+        key_store.delete().await.unwrap();
+        assert_eq!(key_store.get().await.unwrap(), None);
+        assert_eq!(other.get().await.unwrap(), Some(b"work".to_vec()));
+        key_store.set(b"other".to_vec()).await.unwrap();
+        other.delete().await.unwrap();
+        assert_eq!(other.get().await.unwrap(), None);
+        assert_eq!(key_store.get().await.unwrap(), Some(b"other".to_vec()));
+
+        // locked again, and the user dismisses the prompt
+        {
+            let mut state = state.lock().unwrap();
+            state.locked = true;
+            state.dismiss = true;
+        }
+        assert!(matches!(
+            key_store.get().await,
+            Err(KeyStoreError::Cancelled)
+        ));
+
+        state.lock().unwrap().dismiss = false;
+        assert_eq!(key_store.get().await.unwrap(), Some(b"other".to_vec()));
+        key_store.delete().await.unwrap();
+        assert_eq!(key_store.get().await.unwrap(), None);
+        assert!(state.lock().unwrap().items.is_empty());
+    }
+}
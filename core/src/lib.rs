@@ -1,86 +1,586 @@
 use std::{
-    sync::{Arc, LazyLock, Once},
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::anyhow;
-use bridge::{Token, TokenAlg, TokenDetail, TokenResult};
-use enc::{decrypt_secret, encrypt_secret};
+use enc::{
+    blind_index, decrypt_secret, derive_index_key, encrypt_secret, generate_data_key,
+    generate_recovery_key, normalize_recovery_key, unwrap_key, wrap_key, KEY_ITERATIONS,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::sync::{OnceCell, RwLock};
 use totp_rs::{Secret, TOTP};
-use tracing_subscriber::{layer::SubscriberExt, Registry};
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, reload, Registry};
 
-use config::Config;
-use db::{tokens::TokenData, Database, Db};
-use error::Error;
-use logger::{FFILogLayer, Logger};
+use config::BlockingKeyStore;
+use db::{
+    key_slots::{self, KeySlotData},
+    tokens::{self, TokenData},
+    Database, Db,
+};
+use logger::{
+    file::{FileLogger, MAX_LOG_FILES, MAX_LOG_FILE_SIZE},
+    FFILogLayer, LogLevel, LogSinks, Logger, LoggerHandle, Redactor,
+};
+use vault::{TokenMetadata, Vault};
+use zeroize::Zeroizing;
 
 mod bridge;
 mod config;
 mod db;
+mod diagnostics;
 mod enc;
 mod error;
+mod key_store;
 mod logger;
+mod runtime;
+mod vault;
 
-uniffi::setup_scaffolding!();
+pub use bridge::{
+    KeySlot, KeySlotKind, MigrationStatus, NewRecoveryKey, RecoveryCode, Snapshot, Token, TokenAlg,
+    TokenDetail, TokenOrder, TokenResult,
+};
+pub use config::{AsyncKeyStore, Config, KeyStore, KeyStoreError};
+pub use error::Error;
+#[cfg(feature = "secret-service")]
+pub use key_store::SecretServiceKeyStore;
+#[cfg(feature = "passphrase-prompt")]
+pub use key_store::{read_secret, PassphraseKeyStore};
+pub use key_store::{EnvKeyStore, MemoryKeyStore, PASSPHRASE_ENV};
+#[cfg(feature = "key-file")]
+pub use key_store::{KdfParams, KeyFileStore};
+pub use runtime::RuntimeConfig;
 
-static RT: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .expect("failed to initialize tokio runtime")
-});
+uniffi::setup_scaffolding!();
 
-pub(crate) fn rt() -> &'static tokio::runtime::Runtime {
-    &*RT
+struct Logging {
+    sinks: Arc<LogSinks>,
+    level: reload::Handle<LevelFilter, Registry>,
+    /// Directory of the on-disk log set up by `init_logger`, and the id of
+    /// the sink writing to it.
+    log_file: Mutex<Option<(PathBuf, u64)>>,
 }
 
-static INIT_LOGGER: Once = Once::new();
+static LOGGING: OnceLock<Logging> = OnceLock::new();
 
-#[uniffi::export]
-pub fn init_logger(logger: Arc<dyn Logger>) {
-    INIT_LOGGER.call_once(|| {
-        let subscriber = Registry::default().with(FFILogLayer(logger));
+/// Installs the global subscriber on first use. Loggers are swapped in and
+/// out of its sinks afterwards, since a global subscriber can only be set
+/// once per process.
+fn logging() -> &'static Logging {
+    LOGGING.get_or_init(|| {
+        let (filter, level) = reload::Layer::new(LevelFilter::TRACE);
+        let sinks = Arc::new(LogSinks::default());
+        let subscriber = Registry::default()
+            .with(filter)
+            .with(FFILogLayer::new(sinks.clone()));
         tracing::subscriber::set_global_default(subscriber)
             .expect("failed to set global log subscriber");
+
+        Logging {
+            sinks,
+            level,
+            log_file: Mutex::new(None),
+        }
+    })
+}
+
+/// Makes `logger` the only log sink, passing through events up to
+/// `max_level` (everything if omitted). Values of fields that look
+/// sensitive (secrets, accounts, keys) are masked unless the field name is
+/// in `allowed_fields`. Calling it again replaces the previous setup.
+///
+/// With `log_dir`, the same redacted records are also written there as
+/// rotated JSON-lines files, which `export_diagnostics` picks up.
+///
+/// The logger stays registered until the returned handle is dropped.
+#[uniffi::export(default(max_level = None, allowed_fields = [], log_dir = None))]
+pub fn init_logger(
+    logger: Arc<dyn Logger>,
+    max_level: Option<LogLevel>,
+    allowed_fields: Vec<String>,
+    log_dir: Option<String>,
+) -> Arc<LoggerHandle> {
+    let logging = logging();
+    logging.sinks.clear();
+    logging.sinks.set_redactor(Redactor::new(allowed_fields));
+    set_log_level(max_level.unwrap_or(LogLevel::Trace));
+
+    *logging.log_file.lock().unwrap() = log_dir.map(|dir| {
+        let dir = PathBuf::from(dir);
+        let file_logger = FileLogger::new(dir.clone(), MAX_LOG_FILE_SIZE, MAX_LOG_FILES);
+        let id = logging.sinks.add(Arc::new(file_logger));
+        (dir, id)
     });
+
+    LoggerHandle::new(logging.sinks.clone(), logger)
+}
+
+/// Registers an additional log sink next to the ones set up by
+/// `init_logger`, until the returned handle is dropped.
+#[uniffi::export]
+pub fn add_logger(logger: Arc<dyn Logger>) -> Arc<LoggerHandle> {
+    LoggerHandle::new(logging().sinks.clone(), logger)
+}
+
+/// Changes the max level of logged events.
+#[uniffi::export]
+pub fn set_log_level(max_level: LogLevel) {
+    if let Err(e) = logging().level.reload(LevelFilter::from(max_level)) {
+        tracing::error!(error = %e, "failed to change log level");
+    }
+}
+
+fn log_dir() -> Option<PathBuf> {
+    let log_file = LOGGING.get()?.log_file.lock().unwrap();
+    log_file.as_ref().map(|(dir, _)| dir.clone())
+}
+
+async fn user_key(key_store: &dyn AsyncKeyStore) -> Result<Zeroizing<Vec<u8>>, Error> {
+    match key_store.get().await {
+        Ok(Some(user_key)) => Ok(Zeroizing::new(user_key)),
+        Ok(None) => {
+            tracing::error!("no user_key found");
+            Err(Error::InternalError("no user key found".into()))
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to get user_key");
+            Err(e.into())
+        }
+    }
+}
+
+/// Tries `secret` on each of `slots` until one unwraps.
+fn unwrap_data_key<'a>(
+    secret: &[u8],
+    slots: impl Iterator<Item = &'a key_slots::KeySlot>,
+) -> Result<Zeroizing<Vec<u8>>, Error> {
+    for slot in slots {
+        match unwrap_key(secret, slot.data.iterations, &slot.data.wrapped_key) {
+            Err(Error::DecryptError) => continue,
+            result => return result,
+        }
+    }
+    Err(Error::DecryptError)
 }
 
 pub struct Auth2 {
-    pub db: Arc<dyn Database>,
-    config: Config,
+    database_url: String,
+    db: OnceCell<Arc<dyn Database>>,
+    key_store: Arc<dyn AsyncKeyStore>,
+    reset_token: Mutex<Option<String>>,
+    vault: RwLock<Option<Vault>>,
 }
 
 impl Auth2 {
     pub async fn new(config: Config) -> Result<Arc<Self>, Error> {
-        let db = Db::new(config.database_url.clone())?;
-        Ok(Arc::new(Self { db, config }))
+        let key_store = Arc::new(BlockingKeyStore(config.key_store));
+        Self::with_async_key_store(config.database_url, key_store).await
+    }
+
+    pub async fn with_async_key_store(
+        database_url: String,
+        key_store: Arc<dyn AsyncKeyStore>,
+    ) -> Result<Arc<Self>, Error> {
+        #[cfg(not(feature = "sqlcipher"))]
+        let db = OnceCell::new_with(Some(Db::new(database_url.clone())? as Arc<dyn Database>));
+
+        // The whole database is encrypted, so it can only be opened with the
+        // key, which the user may not have created yet.
+        #[cfg(feature = "sqlcipher")]
+        let db = OnceCell::new();
+
+        Ok(Arc::new(Self {
+            database_url,
+            db,
+            key_store,
+            reset_token: Mutex::new(None),
+            vault: RwLock::new(None),
+        }))
+    }
+
+    /// The database, opened on first use with SQLCipher.
+    pub async fn db(&self) -> Result<&Arc<dyn Database>, Error> {
+        self.db.get_or_try_init(|| self.open_db()).await
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    async fn open_db(&self) -> Result<Arc<dyn Database>, Error> {
+        Ok(Db::new(self.database_url.clone())?)
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    async fn open_db(&self) -> Result<Arc<dyn Database>, Error> {
+        let user_key = self.user_key().await?;
+        let key = enc::derive_database_key(&user_key);
+        Ok(Db::new_encrypted(self.database_url.clone(), key).await?)
+    }
+
+    async fn user_key(&self) -> Result<Zeroizing<Vec<u8>>, Error> {
+        user_key(self.key_store.as_ref()).await
+    }
+
+    /// Unwraps the data key with the key store's key and loads the
+    /// decrypted account and service of every token into memory. Tokens
+    /// stored before metadata was encrypted get encrypted here.
+    pub async fn unlock(&self) -> Result<(), Error> {
+        if self.vault.read().await.is_some() {
+            return Ok(());
+        }
+        let mut vault = self.vault.write().await;
+        if vault.is_some() {
+            return Ok(());
+        }
+
+        let data_key = self.open_data_key().await?;
+        *vault = Some(self.load_vault(data_key).await?);
+        Ok(())
+    }
+
+    /// Unlocks with a recovery key from `add_recovery_key`, for when the key
+    /// store's key is lost. Add a new slot for it afterwards.
+    pub async fn unlock_with_recovery_key(&self, recovery_key: String) -> Result<(), Error> {
+        let recovery_key = normalize_recovery_key(&recovery_key);
+        let slots = self.db().await?.list_key_slots().await?;
+        let data_key = unwrap_data_key(
+            recovery_key.as_bytes(),
+            slots
+                .iter()
+                .filter(|slot| slot.data.kind == key_slots::KeySlotKind::RecoveryKey),
+        )?;
+
+        let mut vault = self.vault.write().await;
+        *vault = Some(self.load_vault(data_key).await?);
+        Ok(())
+    }
+
+    /// The data key, unwrapped with the key store's key. A vault without
+    /// key slots gets its first one here, recorded as a passphrase: for a
+    /// new vault it wraps a random data key, and for one from before key
+    /// slots the user key itself, so nothing has to be re-encrypted. That
+    /// one must decrypt what is already stored, or a wrong key would be
+    /// locked in as the passphrase.
+    async fn open_data_key(&self) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let user_key = self.user_key().await?;
+
+        let mut slots = self.db().await?.list_key_slots().await?;
+        if slots.is_empty() {
+            let data_key = match self.db().await?.encrypted_sample().await? {
+                Some(sample) => {
+                    decrypt_secret(&user_key, &sample)?;
+                    user_key.clone()
+                }
+                None => generate_data_key(),
+            };
+            let slot = KeySlotData {
+                kind: key_slots::KeySlotKind::Passphrase,
+                label: None,
+                iterations: KEY_ITERATIONS,
+                wrapped_key: wrap_key(&user_key, KEY_ITERATIONS, &data_key)?,
+            };
+            if self.db().await?.add_first_key_slot(slot).await?.is_some() {
+                tracing::info!("created the first key slot");
+                return Ok(data_key);
+            }
+            // another process got there first
+            slots = self.db().await?.list_key_slots().await?;
+        }
+
+        unwrap_data_key(
+            &user_key,
+            slots
+                .iter()
+                .filter(|slot| slot.data.kind != key_slots::KeySlotKind::RecoveryKey),
+        )
+    }
+
+    async fn load_vault(&self, data_key: Zeroizing<Vec<u8>>) -> Result<Vault, Error> {
+        let index_key = derive_index_key(&data_key);
+
+        let mut metadata = HashMap::new();
+        for item in self
+            .db()
+            .await?
+            .list_tokens(tokens::TokenOrder::Created)
+            .await?
+        {
+            let meta = if item.metadata_encrypted {
+                vault::open(&data_key, &item.account, item.service.as_deref())?
+            } else {
+                tracing::info!(id = item.id, "encrypting token metadata");
+                let mut data = TokenData {
+                    account: item.account.clone(),
+                    service: item.service.clone(),
+                    ..Default::default()
+                };
+                vault::seal(&data_key, index_key.as_ref(), &mut data)?;
+                self.db()
+                    .await?
+                    .update_token_metadata(
+                        item.id,
+                        data.account,
+                        data.service,
+                        data.account_index.unwrap_or_default(),
+                        data.service_index,
+                    )
+                    .await?;
+                TokenMetadata {
+                    account: item.account,
+                    service: item.service,
+                }
+            };
+            metadata.insert(item.id, meta);
+        }
+
+        Ok(Vault {
+            data_key,
+            index_key,
+            metadata,
+        })
+    }
+
+    /// Drops the decrypted metadata from memory.
+    pub async fn lock(&self) {
+        *self.vault.write().await = None;
+    }
+
+    /// Locks the vault, closes the connection pool and stops the id
+    /// generator. The database can't be used afterwards.
+    pub async fn shutdown(&self) {
+        self.lock().await;
+        if let Some(db) = self.db.get() {
+            db.close().await;
+        }
+    }
+
+    async fn data_key(&self) -> Result<Zeroizing<Vec<u8>>, Error> {
+        self.unlock().await?;
+        match self.vault.read().await.as_ref() {
+            Some(vault) => Ok(vault.data_key.clone()),
+            None => Err(Error::InternalError("vault is locked".into())),
+        }
+    }
+
+    async fn index_key(&self) -> Result<Zeroizing<[u8; 32]>, Error> {
+        self.unlock().await?;
+        match self.vault.read().await.as_ref() {
+            Some(vault) => Ok(vault.index_key.clone()),
+            None => Err(Error::InternalError("vault is locked".into())),
+        }
+    }
+
+    /// Plaintext account and service of a token row, from the cache when
+    /// possible.
+    async fn token_metadata(
+        &self,
+        id: u64,
+        account: &str,
+        service: Option<&str>,
+        encrypted: bool,
+    ) -> Result<TokenMetadata, Error> {
+        self.unlock().await?;
+        if let Some(meta) = self
+            .vault
+            .read()
+            .await
+            .as_ref()
+            .and_then(|vault| vault.metadata.get(&id).cloned())
+        {
+            return Ok(meta);
+        }
+
+        // added by another process since the vault was unlocked
+        let meta = if encrypted {
+            vault::open(&self.data_key().await?, account, service)?
+        } else {
+            TokenMetadata {
+                account: account.to_owned(),
+                service: service.map(ToOwned::to_owned),
+            }
+        };
+        if let Some(vault) = self.vault.write().await.as_mut() {
+            vault.metadata.insert(id, meta.clone());
+        }
+        Ok(meta)
+    }
+
+    async fn list_item(&self, item: tokens::TokenListItem) -> Result<Token, Error> {
+        let meta = self
+            .token_metadata(
+                item.id,
+                &item.account,
+                item.service.as_deref(),
+                item.metadata_encrypted,
+            )
+            .await?;
+        Ok(Token {
+            account: meta.account,
+            service: meta.service,
+            ..Token::from(item)
+        })
+    }
+
+    async fn insert_token(&self, mut data: TokenData) -> Result<TokenDetail, Error> {
+        let data_key = self.data_key().await?;
+        let index_key = self.index_key().await?;
+
+        let meta = TokenMetadata {
+            account: data.account.clone(),
+            service: data.service.clone(),
+        };
+        vault::seal(&data_key, index_key.as_ref(), &mut data)?;
+
+        let id = self.db().await?.add_token(data).await?;
+        if let Some(vault) = self.vault.write().await.as_mut() {
+            vault.metadata.insert(id, meta);
+        }
+
+        let Some(token) = self.token_detail(id).await? else {
+            return Err(anyhow!("token not found").into());
+        };
+        Ok(token)
     }
 
     pub async fn db_is_migration_available(&self) -> Result<bool, Error> {
-        Ok(self.db.is_migration_available().await?)
+        Ok(self.db().await?.is_migration_available().await?)
     }
 
     pub async fn db_run_migration(&self) -> Result<(), Error> {
-        Ok(self.db.run_migration().await?)
+        Ok(self.db().await?.run_migration().await?)
+    }
+
+    pub async fn db_migration_status(&self) -> Result<Vec<MigrationStatus>, Error> {
+        Ok(self
+            .db()
+            .await?
+            .migration_status()
+            .await?
+            .into_iter()
+            .map(MigrationStatus::from)
+            .collect())
     }
 
-    pub async fn db_reset(&self) -> Result<(), Error> {
-        Ok(self.db.reset_database().await?)
+    /// Reverts migrations newer than `version`. Refuses to go below
+    /// EncryptTokenMetadata while any token's metadata is encrypted, since
+    /// the older schema would show the ciphertext as account names.
+    /// The same goes for CreateKeySlots once the data key is a random one
+    /// rather than the user key, as the tokens couldn't be decrypted without
+    /// the slots.
+    pub async fn db_rollback_to(&self, version: i64) -> Result<(), Error> {
+        if version < key_slots::MIGRATION_VERSION && self.has_random_data_key().await? {
+            return Err(Error::MigrationError(format!(
+                "the data key is kept in key slots, can't roll back below {}",
+                key_slots::MIGRATION_VERSION
+            )));
+        }
+
+        self.lock().await;
+        self.db()
+            .await?
+            .rollback_to(version)
+            .await
+            .map_err(|e| Error::MigrationError(e.to_string()))
+    }
+
+    async fn has_random_data_key(&self) -> Result<bool, Error> {
+        let db = self.db().await?;
+        let applied = db
+            .migration_status()
+            .await?
+            .iter()
+            .any(|m| m.version == key_slots::MIGRATION_VERSION && m.applied);
+        if !applied {
+            return Ok(false);
+        }
+        let slots = db.list_key_slots().await?;
+        if slots.is_empty() {
+            return Ok(false);
+        }
+
+        let user_key = self.user_key().await?;
+        let data_key = unwrap_data_key(
+            &user_key,
+            slots
+                .iter()
+                .filter(|slot| slot.data.kind != key_slots::KeySlotKind::RecoveryKey),
+        )?;
+        Ok(data_key != user_key)
+    }
+
+    /// Fixes `MigrationMismatchError` by re-recording the embedded checksums,
+    /// as long as the schema is what those migrations produce.
+    pub async fn db_repair_migrations(&self) -> Result<Vec<i64>, Error> {
+        self.db()
+            .await?
+            .repair_migrations()
+            .await
+            .map_err(|e| Error::MigrationError(e.to_string()))
+    }
+
+    pub async fn db_list_snapshots(&self) -> Result<Vec<Snapshot>, Error> {
+        Ok(self
+            .db()
+            .await?
+            .list_snapshots()
+            .await?
+            .into_iter()
+            .map(Snapshot::from)
+            .collect())
+    }
+
+    pub async fn db_restore_snapshot(&self, name: String) -> Result<(), Error> {
+        self.lock().await;
+        Ok(self.db().await?.restore_snapshot(name).await?)
+    }
+
+    /// Writes a JSON report with the schema version, token counts and the
+    /// recent on-disk logs to `path`, for attaching to bug reports.
+    pub async fn export_diagnostics(&self, path: String) -> Result<(), Error> {
+        let diagnostics =
+            diagnostics::collect(self.db().await?.as_ref(), log_dir().as_deref()).await;
+        let json = serde_json::to_vec_pretty(&diagnostics)?;
+        tokio::fs::write(path, json)
+            .await
+            .map_err(|e| Error::InternalError(e.to_string()))
+    }
+
+    /// Issues a one-time token that has to be passed to `db_reset`, so the
+    /// database can't be wiped by a single stray call.
+    pub fn db_reset_token(&self) -> String {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        *self.reset_token.lock().unwrap() = Some(token.clone());
+        token
+    }
+
+    pub async fn db_reset(&self, confirmation: String) -> Result<(), Error> {
+        let expected = self.reset_token.lock().unwrap().take();
+        if expected.as_deref() != Some(confirmation.as_str()) {
+            tracing::error!("database reset requested with invalid confirmation token");
+            return Err(Error::InternalError(
+                "invalid reset confirmation token".into(),
+            ));
+        }
+        self.lock().await;
+        Ok(self.db().await?.reset_database().await?)
     }
 
     pub async fn add_token_from_url(&self, url: String) -> Result<TokenDetail, Error> {
-        let Some(user_key) = self.config.key_store.get() else {
-            tracing::error!("no user_key found");
-            return Err(Error::InternalError("no user key found".into()));
-        };
+        let data_key = self.data_key().await?;
 
+        let url = Zeroizing::new(url);
         let totp = TOTP::from_url_unchecked(&url).map_err(anyhow::Error::from)?;
-        let secret = totp.get_secret_base32();
-        let secret = encrypt_secret(user_key, secret)?;
+        let secret = Zeroizing::new(totp.get_secret_base32());
+        let secret = encrypt_secret(&data_key, &secret)?;
 
         let data = TokenData {
-            account: totp.account_name,
-            service: totp.issuer,
+            account: totp.account_name.clone(),
+            service: totp.issuer.clone(),
             secret,
             algorithm: match totp.algorithm {
                 totp_rs::Algorithm::SHA1 => db::tokens::TokenAlg::Sha1,
@@ -89,14 +589,10 @@ impl Auth2 {
             },
             digits: totp.digits as u8,
             period: totp.step as u32,
+            ..Default::default()
         };
 
-        let id = self.db.add_token(data).await?;
-        let Some(token) = self.db.token_detail(id).await? else {
-            return Err(anyhow!("token not found").into());
-        };
-
-        Ok(token.into())
+        self.insert_token(data).await
     }
 
     pub async fn add_token(
@@ -108,12 +604,10 @@ impl Auth2 {
         digits: Option<u8>,
         period: Option<u32>,
     ) -> Result<TokenDetail, Error> {
-        let Some(user_key) = self.config.key_store.get() else {
-            tracing::error!("no user_key found");
-            return Err(Error::InternalError("no user key found".into()));
-        };
+        let data_key = self.data_key().await?;
 
-        let secret = encrypt_secret(user_key, secret)?;
+        let secret = Zeroizing::new(secret);
+        let secret = encrypt_secret(&data_key, &secret)?;
 
         let mut data = TokenData {
             account,
@@ -135,48 +629,98 @@ impl Auth2 {
             data.period = period;
         }
 
-        let id = self.db.add_token(data).await?;
-        let Some(token) = self.db.token_detail(id).await? else {
-            return Err(anyhow!("token not found").into());
-        };
-
-        Ok(token.into())
+        self.insert_token(data).await
     }
 
     pub async fn remove_token(&self, id: u64) -> Result<(), Error> {
-        self.db.remove_token(id).await
+        self.db().await?.remove_token(id).await?;
+        if let Some(vault) = self.vault.write().await.as_mut() {
+            vault.metadata.remove(&id);
+        }
+        Ok(())
     }
 
-    pub async fn list_tokens(&self) -> Result<Vec<Token>, Error> {
-        Ok(self
-            .db
-            .list_tokens()
+    pub async fn list_tokens(&self, order: Option<TokenOrder>) -> Result<Vec<Token>, Error> {
+        let items = self
+            .db()
             .await?
-            .into_iter()
-            .map(Token::from)
-            .collect())
+            .list_tokens(order.map(Into::into).unwrap_or_default())
+            .await?;
+
+        let mut tokens = Vec::with_capacity(items.len());
+        for item in items {
+            tokens.push(self.list_item(item).await?);
+        }
+        Ok(tokens)
+    }
+
+    /// Finds tokens by exact account name (ignoring case), optionally
+    /// narrowed down to a service, using the blind indexes.
+    pub async fn find_tokens(
+        &self,
+        account: String,
+        service: Option<String>,
+    ) -> Result<Vec<Token>, Error> {
+        let index_key = self.index_key().await?;
+        let items = self
+            .db()
+            .await?
+            .find_tokens(
+                blind_index(index_key.as_ref(), &account),
+                service.map(|service| blind_index(index_key.as_ref(), &service)),
+            )
+            .await?;
+
+        let mut tokens = Vec::with_capacity(items.len());
+        for item in items {
+            tokens.push(self.list_item(item).await?);
+        }
+        Ok(tokens)
     }
 
     pub async fn token_detail(&self, id: u64) -> Result<Option<TokenDetail>, Error> {
-        Ok(self.db.token_detail(id).await?.map(TokenDetail::from))
+        let Some(token) = self.db().await?.token_detail(id).await? else {
+            return Ok(None);
+        };
+        let meta = self
+            .token_metadata(
+                token.id,
+                &token.data.account,
+                token.data.service.as_deref(),
+                token.metadata_encrypted,
+            )
+            .await?;
+
+        Ok(Some(TokenDetail {
+            account: meta.account,
+            service: meta.service,
+            ..TokenDetail::from(token)
+        }))
     }
 
-    pub async fn generate_current(&self, id: u64) -> Result<TokenResult, Error> {
-        let Some(token) = self.db.token_detail(id).await? else {
+    async fn totp(&self, id: u64) -> Result<TOTP, Error> {
+        let Some(token) = self.db().await?.token_detail(id).await? else {
             return Err(Error::InternalError("no entry found".into()));
         };
 
-        let Some(user_key) = self.config.key_store.get() else {
-            tracing::error!("no user_key found");
-            return Err(Error::InternalError("no user key found".into()));
-        };
+        let meta = self
+            .token_metadata(
+                token.id,
+                &token.data.account,
+                token.data.service.as_deref(),
+                token.metadata_encrypted,
+            )
+            .await?;
+
+        let data_key = self.data_key().await?;
 
-        let secret = decrypt_secret(user_key, token.data.secret)?;
-        let secret = Secret::Encoded(secret)
+        let mut secret = decrypt_secret(&data_key, &token.data.secret)?;
+        // moved rather than copied: `Secret` zeroizes it on drop
+        let secret = Secret::Encoded(std::mem::take(&mut *secret))
             .to_bytes()
             .map_err(anyhow::Error::from)?;
 
-        let totp = TOTP::new_unchecked(
+        Ok(TOTP::new_unchecked(
             match token.data.algorithm {
                 db::tokens::TokenAlg::Sha1 => totp_rs::Algorithm::SHA1,
                 db::tokens::TokenAlg::Sha256 => totp_rs::Algorithm::SHA256,
@@ -186,10 +730,21 @@ impl Auth2 {
             1,
             token.data.period as u64,
             secret,
-            token.data.service,
-            token.data.account,
-        );
+            meta.service,
+            meta.account,
+        ))
+    }
 
+    pub async fn generate_current(&self, id: u64) -> Result<TokenResult, Error> {
+        let result = self.current_code(id).await?;
+        self.db().await?.record_token_use(id).await?;
+        Ok(result)
+    }
+
+    /// Like `generate_current`, but doesn't count as a use of the token, for
+    /// displays that keep every code up to date.
+    pub async fn current_code(&self, id: u64) -> Result<TokenResult, Error> {
+        let totp = self.totp(id).await?;
         let current = totp.generate_current().map_err(anyhow::Error::from)?;
 
         let ts = SystemTime::now()
@@ -203,4 +758,577 @@ impl Auth2 {
             expires: (next - ts) as u32,
         })
     }
+
+    /// Rebuilds the otpauth URL of a token, secret included, for exporting
+    /// it to another authenticator.
+    pub async fn token_url(&self, id: u64) -> Result<Zeroizing<String>, Error> {
+        let totp = self.totp(id).await?;
+        Ok(Zeroizing::new(totp.get_url()))
+    }
+
+    pub async fn record_token_use(&self, id: u64) -> Result<(), Error> {
+        self.db().await?.record_token_use(id).await
+    }
+
+    pub async fn set_pinned(&self, id: u64, pinned: bool) -> Result<(), Error> {
+        self.db().await?.set_token_pinned(id, pinned).await
+    }
+
+    pub async fn add_recovery_codes(
+        &self,
+        token_id: u64,
+        codes: Vec<String>,
+    ) -> Result<Vec<RecoveryCode>, Error> {
+        if self.db().await?.token_detail(token_id).await?.is_none() {
+            return Err(Error::InternalError("no entry found".into()));
+        }
+
+        let data_key = self.data_key().await?;
+
+        let encrypted = codes
+            .iter()
+            .map(|code| encrypt_secret(&data_key, code))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let ids = self
+            .db()
+            .await?
+            .add_recovery_codes(token_id, encrypted)
+            .await?;
+
+        Ok(ids
+            .into_iter()
+            .zip(codes)
+            .map(|(id, code)| RecoveryCode { id, code })
+            .collect())
+    }
+
+    pub async fn use_recovery_code(&self, id: u64) -> Result<(), Error> {
+        if !self.db().await?.use_recovery_code(id).await? {
+            return Err(Error::InternalError("no unused recovery code found".into()));
+        }
+        Ok(())
+    }
+
+    pub async fn list_recovery_codes(&self, token_id: u64) -> Result<Vec<RecoveryCode>, Error> {
+        let data_key = self.data_key().await?;
+
+        self.db()
+            .await?
+            .list_recovery_codes(token_id)
+            .await?
+            .into_iter()
+            .map(|v| {
+                Ok(RecoveryCode {
+                    id: v.id,
+                    code: decrypt_secret(&data_key, &v.code)?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn list_key_slots(&self) -> Result<Vec<KeySlot>, Error> {
+        Ok(self
+            .db()
+            .await?
+            .list_key_slots()
+            .await?
+            .into_iter()
+            .map(KeySlot::from)
+            .collect())
+    }
+
+    /// Lets `secret` unlock the vault too, e.g. a key kept by the platform
+    /// behind biometrics. Tokens aren't re-encrypted.
+    pub async fn add_key_slot(
+        &self,
+        kind: KeySlotKind,
+        secret: Vec<u8>,
+        label: Option<String>,
+    ) -> Result<KeySlot, Error> {
+        let secret = Zeroizing::new(secret);
+        if matches!(kind, KeySlotKind::RecoveryKey) {
+            return Err(Error::InternalError(
+                "recovery keys are generated by add_recovery_key".into(),
+            ));
+        }
+        if secret.is_empty() {
+            return Err(Error::InternalError("empty key slot secret".into()));
+        }
+        self.insert_key_slot(kind.into(), &secret, label).await
+    }
+
+    /// Generates a recovery key that unlocks the vault through
+    /// `unlock_with_recovery_key`. It's returned only this once.
+    pub async fn add_recovery_key(&self, label: Option<String>) -> Result<NewRecoveryKey, Error> {
+        let recovery_key = generate_recovery_key();
+        let slot = self
+            .insert_key_slot(
+                key_slots::KeySlotKind::RecoveryKey,
+                normalize_recovery_key(&recovery_key).as_bytes(),
+                label,
+            )
+            .await?;
+        Ok(NewRecoveryKey {
+            slot,
+            recovery_key: recovery_key.to_string(),
+        })
+    }
+
+    /// Removes a slot so its secret no longer unlocks the vault. The last
+    /// slot that isn't a recovery key can't be removed, or only
+    /// `unlock_with_recovery_key` could open the vault.
+    pub async fn revoke_key_slot(&self, id: u64) -> Result<(), Error> {
+        self.unlock().await?;
+        if !self.db().await?.remove_key_slot(id).await? {
+            return Err(Error::InternalError(
+                "no key slot found, or it is the last one that isn't a recovery key".into(),
+            ));
+        }
+        tracing::info!(id, "revoked key slot");
+        Ok(())
+    }
+
+    async fn insert_key_slot(
+        &self,
+        kind: key_slots::KeySlotKind,
+        secret: &[u8],
+        label: Option<String>,
+    ) -> Result<KeySlot, Error> {
+        let data_key = self.data_key().await?;
+        let slot = self
+            .db()
+            .await?
+            .add_key_slot(KeySlotData {
+                kind,
+                label,
+                iterations: KEY_ITERATIONS,
+                wrapped_key: wrap_key(secret, KEY_ITERATIONS, &data_key)?,
+            })
+            .await?;
+        tracing::info!(id = slot.id, ?kind, "added key slot");
+        Ok(slot.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        add_logger,
+        config::{AsyncKeyStore, Config, KeyStoreError},
+        db::key_slots,
+        db::tokens::{TokenData, TokenOrder},
+        enc::{decrypt_secret, encrypt_secret},
+        error::Error,
+        init_logger,
+        key_store::MemoryKeyStore,
+        logger::{LogRecord, Logger},
+        Auth2, KeySlotKind,
+    };
+
+    async fn auth2() -> Arc<Auth2> {
+        let auth2 = Auth2::new(Config::new("sqlite::memory:", MemoryKeyStore::new("test")))
+            .await
+            .unwrap();
+        auth2.db_run_migration().await.unwrap();
+        auth2
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_metadata() {
+        let auth2 = auth2().await;
+
+        // stored before metadata encryption
+        let legacy = auth2
+            .db()
+            .await
+            .unwrap()
+            .add_token(TokenData {
+                account: "legacy".into(),
+                service: Some("Old".into()),
+                secret: encrypt_secret(b"test", "JBSWY3DPEHPK3PXP").unwrap(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let token = auth2
+            .add_token(
+                "dameleon".into(),
+                Some("Example".into()),
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(token.account, "dameleon");
+        assert_eq!(token.service.as_deref(), Some("Example"));
+
+        let items = auth2
+            .db()
+            .await
+            .unwrap()
+            .list_tokens(TokenOrder::Created)
+            .await
+            .unwrap();
+        assert!(items.iter().all(|item| item.metadata_encrypted));
+        assert!(items.iter().all(|item| item.account != "dameleon"));
+        assert!(items.iter().all(|item| item.account != "legacy"));
+
+        auth2.lock().await;
+        let tokens = auth2.list_tokens(None).await.unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].id, legacy);
+        assert_eq!(tokens[0].account, "legacy");
+        assert_eq!(tokens[1].account, "dameleon");
+
+        let found = auth2.find_tokens("DAMELEON".into(), None).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, token.id);
+        let found = auth2
+            .find_tokens("dameleon".into(), Some("other".into()))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 0);
+
+        let detail = auth2.token_detail(legacy).await.unwrap().unwrap();
+        assert_eq!(detail.service.as_deref(), Some("Old"));
+
+        auth2.generate_current(token.id).await.unwrap();
+    }
+
+    #[derive(Debug, Default)]
+    struct TestLogger(Mutex<Vec<String>>);
+
+    impl Logger for TestLogger {
+        fn log(&self, record: LogRecord) {
+            // other tests log through the global subscriber too
+            if record.target == "test_reinit_logger" {
+                self.0.lock().unwrap().push(record.message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reinit_logger() {
+        let first = Arc::new(TestLogger::default());
+        let second = Arc::new(TestLogger::default());
+        let extra = Arc::new(TestLogger::default());
+
+        let _first_handle = init_logger(first.clone(), None, vec![], None);
+        tracing::info!(target: "test_reinit_logger", "one");
+
+        let second_handle = init_logger(second.clone(), None, vec![], None);
+        let extra_handle = add_logger(extra.clone());
+        tracing::info!(target: "test_reinit_logger", "two");
+
+        drop(extra_handle);
+        tracing::info!(target: "test_reinit_logger", "three");
+        drop(second_handle);
+        tracing::info!(target: "test_reinit_logger", "four");
+
+        assert_eq!(*first.0.lock().unwrap(), ["one"]);
+        assert_eq!(*second.0.lock().unwrap(), ["two", "three"]);
+        assert_eq!(*extra.0.lock().unwrap(), ["two"]);
+    }
+
+    #[tokio::test]
+    async fn test_token_url() {
+        let auth2 = auth2().await;
+        let token = auth2
+            .add_token(
+                "dameleon".into(),
+                Some("GitHub".into()),
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let url = auth2.token_url(token.id).await.unwrap();
+        assert!(url.starts_with("otpauth://totp/GitHub:dameleon?"));
+        assert!(url.contains("secret=JBSWY3DPEHPK3PXP"));
+
+        let imported = auth2.add_token_from_url(url.to_string()).await.unwrap();
+        assert_eq!(imported.account, "dameleon");
+        assert_eq!(
+            auth2.generate_current(token.id).await.unwrap().current,
+            auth2.generate_current(imported.id).await.unwrap().current
+        );
+    }
+
+    /// Holds raw bytes, and fails with `Cancelled` until a key is set.
+    #[derive(Debug, Default)]
+    struct PromptKeyStore(Mutex<Option<Vec<u8>>>);
+
+    #[async_trait::async_trait]
+    impl AsyncKeyStore for PromptKeyStore {
+        async fn get(&self) -> Result<Option<Vec<u8>>, KeyStoreError> {
+            match &*self.0.lock().unwrap() {
+                Some(key) => Ok(Some(key.clone())),
+                None => Err(KeyStoreError::Cancelled),
+            }
+        }
+
+        async fn set(&self, key: Vec<u8>) -> Result<(), KeyStoreError> {
+            *self.0.lock().unwrap() = Some(key);
+            Ok(())
+        }
+
+        async fn delete(&self) -> Result<(), KeyStoreError> {
+            *self.0.lock().unwrap() = None;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_key_store() {
+        let key_store = Arc::new(PromptKeyStore::default());
+        // not valid UTF-8
+        key_store.set(vec![0xff, 0x00, 0xfe]).await.unwrap();
+        let auth2 = Auth2::with_async_key_store("sqlite::memory:".into(), key_store.clone())
+            .await
+            .unwrap();
+        auth2.db_run_migration().await.unwrap();
+
+        let add = || {
+            auth2.add_token(
+                "dameleon".into(),
+                None,
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+        };
+        let token = add().await.unwrap();
+        assert_eq!(auth2.list_tokens(None).await.unwrap().len(), 1);
+
+        // the unlocked vault holds the data key, the store is asked again
+        // once it's locked
+        key_store.delete().await.unwrap();
+        add().await.unwrap();
+        auth2.lock().await;
+        assert!(matches!(
+            add().await,
+            Err(Error::KeyStoreError(KeyStoreError::Cancelled))
+        ));
+
+        key_store.set(b"other".to_vec()).await.unwrap();
+        assert!(matches!(
+            auth2.generate_current(token.id).await,
+            Err(Error::DecryptError)
+        ));
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    async fn open(database_url: &str, key: &[u8]) -> Arc<Auth2> {
+        Auth2::new(Config::new(database_url, MemoryKeyStore::new(key)))
+            .await
+            .unwrap()
+    }
+
+    // with SQLCipher, the database itself is still keyed by the key store
+    #[cfg(not(feature = "sqlcipher"))]
+    #[tokio::test]
+    async fn test_key_slots() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let auth2 = open(&database_url, b"passphrase").await;
+        auth2.db_run_migration().await.unwrap();
+        let token = auth2
+            .add_token(
+                "dameleon".into(),
+                None,
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let slots = auth2.list_key_slots().await.unwrap();
+        assert_eq!(slots.len(), 1);
+        assert!(matches!(slots[0].kind, KeySlotKind::Passphrase));
+
+        let platform = auth2
+            .add_key_slot(
+                KeySlotKind::PlatformKey,
+                b"platform".to_vec(),
+                Some("biometrics".into()),
+            )
+            .await
+            .unwrap();
+        let recovery = auth2.add_recovery_key(None).await.unwrap();
+        assert!(auth2
+            .add_key_slot(KeySlotKind::RecoveryKey, b"recovery".to_vec(), None)
+            .await
+            .is_err());
+        assert_eq!(auth2.list_key_slots().await.unwrap().len(), 3);
+
+        // every slot opens the same tokens
+        let other = open(&database_url, b"platform").await;
+        other.generate_current(token.id).await.unwrap();
+
+        let other = open(&database_url, b"wrong").await;
+        assert!(matches!(
+            other.generate_current(token.id).await,
+            Err(Error::DecryptError)
+        ));
+        // a recovery key doesn't unlock through the key store
+        let other = open(&database_url, recovery.recovery_key.as_bytes()).await;
+        assert!(matches!(other.unlock().await, Err(Error::DecryptError)));
+        assert!(matches!(
+            other.unlock_with_recovery_key("AAAAA".into()).await,
+            Err(Error::DecryptError)
+        ));
+        other
+            .unlock_with_recovery_key(recovery.recovery_key.to_lowercase().replace('-', " "))
+            .await
+            .unwrap();
+        assert_eq!(
+            other.token_detail(token.id).await.unwrap().unwrap().account,
+            "dameleon"
+        );
+
+        auth2.revoke_key_slot(platform.id).await.unwrap();
+        let other = open(&database_url, b"platform").await;
+        assert!(matches!(other.unlock().await, Err(Error::DecryptError)));
+
+        // the recovery key alone can't be all that's left
+        let passphrase = auth2.list_key_slots().await.unwrap()[0].id;
+        assert!(auth2.revoke_key_slot(passphrase).await.is_err());
+
+        auth2.revoke_key_slot(recovery.slot.id).await.unwrap();
+        let last = auth2.list_key_slots().await.unwrap()[0].id;
+        assert_eq!(last, passphrase);
+        assert!(auth2.revoke_key_slot(last).await.is_err());
+        auth2.lock().await;
+        auth2.generate_current(token.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_key_slots_existing_vault() {
+        let auth2 = auth2().await;
+        // stored with the user key, before there were key slots
+        let id = auth2
+            .db()
+            .await
+            .unwrap()
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: encrypt_secret(b"test", "JBSWY3DPEHPK3PXP").unwrap(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(auth2.list_key_slots().await.unwrap().is_empty());
+
+        auth2.generate_current(id).await.unwrap();
+        assert_eq!(auth2.list_key_slots().await.unwrap().len(), 1);
+
+        // and the user key keeps working as the data key for new tokens
+        auth2
+            .add_key_slot(KeySlotKind::Passphrase, b"new".to_vec(), None)
+            .await
+            .unwrap();
+        let token = auth2
+            .add_token(
+                "other".into(),
+                None,
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let secret = auth2
+            .db()
+            .await
+            .unwrap()
+            .token_detail(token.id)
+            .await
+            .unwrap()
+            .unwrap();
+        decrypt_secret(b"test", &secret.data.secret).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_with_key_slots() {
+        let auth2 = auth2().await;
+        auth2.unlock().await.unwrap();
+        let before = auth2
+            .db_migration_status()
+            .await
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|m| m.version < key_slots::MIGRATION_VERSION)
+            .unwrap()
+            .version;
+        assert!(matches!(
+            auth2.db_rollback_to(before).await,
+            Err(Error::MigrationError(_))
+        ));
+        assert_eq!(auth2.list_key_slots().await.unwrap().len(), 1);
+
+        // the user key as the data key still decrypts everything without
+        // the slots
+        let auth2 = self::auth2().await;
+        auth2
+            .db()
+            .await
+            .unwrap()
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: encrypt_secret(b"test", "JBSWY3DPEHPK3PXP").unwrap(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        auth2.unlock().await.unwrap();
+        auth2.db_rollback_to(before).await.unwrap();
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[tokio::test]
+    async fn test_key_slots_existing_vault_wrong_key() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database_url = format!("sqlite://{}/database.db", temp_dir.path().to_str().unwrap());
+
+        let auth2 = open(&database_url, b"passphrase").await;
+        auth2.db_run_migration().await.unwrap();
+        auth2
+            .db()
+            .await
+            .unwrap()
+            .add_token(TokenData {
+                account: "dameleon".into(),
+                secret: encrypt_secret(b"passphrase", "JBSWY3DPEHPK3PXP").unwrap(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        auth2.shutdown().await;
+
+        // a typo on the first unlock doesn't become the passphrase
+        let auth2 = open(&database_url, b"typo").await;
+        assert!(matches!(auth2.unlock().await, Err(Error::DecryptError)));
+        assert!(auth2.list_key_slots().await.unwrap().is_empty());
+        auth2.shutdown().await;
+
+        let auth2 = open(&database_url, b"passphrase").await;
+        auth2.unlock().await.unwrap();
+        assert_eq!(auth2.list_key_slots().await.unwrap().len(), 1);
+        let tokens = auth2.list_tokens(None).await.unwrap();
+        assert_eq!(tokens[0].account, "dameleon");
+    }
 }
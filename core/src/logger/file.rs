@@ -0,0 +1,196 @@
+use std::{
+    fmt, fs,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::{LogRecord, Logger};
+
+pub const LOG_FILE_NAME: &str = "auth2.log";
+pub const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+pub const MAX_LOG_FILES: usize = 5;
+
+/// Writes log records as JSON lines to `<dir>/auth2.log`. Once the file
+/// grows past `max_file_size` it is rotated to `auth2.log.1`, `auth2.log.2`
+/// and so on, keeping at most `max_files` files.
+pub struct FileLogger {
+    dir: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    file: Mutex<Option<LogFile>>,
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+impl FileLogger {
+    pub fn new(dir: impl Into<PathBuf>, max_file_size: u64, max_files: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            max_file_size,
+            max_files: max_files.max(1),
+            file: Mutex::new(None),
+        }
+    }
+
+    fn write(&self, line: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(current) = file.as_ref() {
+            if current.size > 0 && current.size + line.len() as u64 > self.max_file_size {
+                *file = None;
+                self.rotate()?;
+            }
+        }
+
+        let current = match file.as_mut() {
+            Some(current) => current,
+            None => file.insert(self.open()?),
+        };
+        current.file.write_all(line)?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<LogFile> {
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(LOG_FILE_NAME))?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { file, size })
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        for n in (1..self.max_files).rev() {
+            let from = log_file_path(&self.dir, n - 1);
+            if from.exists() {
+                fs::rename(from, log_file_path(&self.dir, n))?;
+            }
+        }
+        // with a single file there is nowhere to rotate to
+        let current = log_file_path(&self.dir, 0);
+        if current.exists() {
+            fs::remove_file(current)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FileLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileLogger")
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+impl Logger for FileLogger {
+    fn log(&self, record: LogRecord) {
+        let Ok(mut line) = serde_json::to_vec(&record) else {
+            return;
+        };
+        line.push(b'\n');
+        // there is nowhere to report a failing log sink
+        let _ = self.write(&line);
+    }
+}
+
+/// `auth2.log` for `n == 0`, otherwise the `n`th rotated file.
+fn log_file_path(dir: &Path, n: usize) -> PathBuf {
+    match n {
+        0 => dir.join(LOG_FILE_NAME),
+        n => dir.join(format!("{}.{}", LOG_FILE_NAME, n)),
+    }
+}
+
+/// Reads the log records in up to `max_files` files in `dir`, oldest
+/// first. Missing files are skipped, as `auth2.log` is after a crash right
+/// after rotating. So are lines that aren't valid JSON, e.g. a line cut off
+/// by a crash.
+pub fn read_logs(dir: &Path, max_files: usize) -> io::Result<Vec<serde_json::Value>> {
+    let paths: Vec<_> = (0..max_files.max(1))
+        .map(|n| log_file_path(dir, n))
+        .filter(|path| path.exists())
+        .collect();
+
+    let mut records = vec![];
+    for path in paths.iter().rev() {
+        let file = BufReader::new(File::open(path)?);
+        for line in file.lines() {
+            if let Ok(record) = serde_json::from_str(&line?) {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tempfile::tempdir;
+
+    use super::{read_logs, FileLogger, LOG_FILE_NAME};
+    use crate::logger::{LogLevel, LogRecord, Logger};
+
+    fn record(message: String) -> LogRecord {
+        LogRecord {
+            level: LogLevel::Info,
+            timestamp: 0,
+            target: "auth2".into(),
+            module: None,
+            message,
+            fields: HashMap::from([("secret".into(), "<redacted>".into())]),
+            spans: vec![],
+        }
+    }
+
+    #[test]
+    fn test_file_logger() {
+        let temp_dir = tempdir().unwrap();
+        let logger = FileLogger::new(temp_dir.path(), 512, 3);
+
+        for i in 0..50 {
+            logger.log(record(format!("message {}", i)));
+        }
+
+        assert!(temp_dir.path().join(LOG_FILE_NAME).exists());
+        assert!(temp_dir.path().join("auth2.log.1").exists());
+        assert!(temp_dir.path().join("auth2.log.2").exists());
+        assert!(!temp_dir.path().join("auth2.log.3").exists());
+        for n in 0..3 {
+            let path = match n {
+                0 => temp_dir.path().join(LOG_FILE_NAME),
+                n => temp_dir.path().join(format!("auth2.log.{}", n)),
+            };
+            assert!(std::fs::metadata(path).unwrap().len() <= 512);
+        }
+
+        let logs = read_logs(temp_dir.path(), 3).unwrap();
+        assert!(!logs.is_empty() && logs.len() < 50);
+        assert_eq!(logs.last().unwrap()["message"], "message 49");
+        assert_eq!(logs[0]["level"], "info");
+        assert_eq!(logs[0]["fields"]["secret"], "<redacted>");
+
+        let messages: Vec<_> = logs
+            .iter()
+            .map(|log| log["message"].as_str().unwrap().to_string())
+            .collect();
+        let mut sorted = messages.clone();
+        sorted.sort_by_key(|m| m[8..].parse::<u32>().unwrap());
+        assert_eq!(messages, sorted);
+
+        // rotated files are still read without the current one
+        std::fs::remove_file(temp_dir.path().join(LOG_FILE_NAME)).unwrap();
+        let rotated = read_logs(temp_dir.path(), 3).unwrap();
+        assert!(!rotated.is_empty() && rotated.len() < logs.len());
+        assert_eq!(rotated[0], logs[0]);
+    }
+}
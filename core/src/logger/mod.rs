@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tracing::{
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{filter::LevelFilter, layer::Context, registry::LookupSpan, Layer};
+
+pub mod file;
+
+/// Field names, or `_`-separated parts of field names, whose values are
+/// masked before they reach the foreign logger.
+const SENSITIVE_FIELDS: &[&str] = &["secret", "account", "key", "passphrase", "code"];
+
+const REDACTED: &str = "<redacted>";
+
+#[uniffi::export(with_foreign)]
+pub trait Logger: Send + Sync + Debug {
+    fn log(&self, record: LogRecord);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, uniffi::Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<&Level> for LogLevel {
+    fn from(level: &Level) -> Self {
+        match *level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            Level::INFO => LogLevel::Info,
+            Level::DEBUG => LogLevel::Debug,
+            Level::TRACE => LogLevel::Trace,
+        }
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, uniffi::Record)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    /// Unix time in milliseconds.
+    pub timestamp: u64,
+    pub target: String,
+    pub module: Option<String>,
+    pub message: String,
+    pub fields: HashMap<String, String>,
+    /// Spans the event was recorded in, outermost first.
+    pub spans: Vec<LogSpan>,
+}
+
+#[derive(Debug, Clone, Serialize, uniffi::Record)]
+pub struct LogSpan {
+    pub name: String,
+    pub fields: HashMap<String, String>,
+}
+
+/// Masks the values of sensitive fields so logs can be shared without
+/// leaking secrets or account names.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    allowed_fields: HashSet<String>,
+}
+
+impl Redactor {
+    /// `allowed_fields` are logged verbatim even if they look sensitive.
+    pub fn new(allowed_fields: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            allowed_fields: allowed_fields.into_iter().collect(),
+        }
+    }
+
+    pub fn is_sensitive(&self, field: &str) -> bool {
+        if self.allowed_fields.contains(field) {
+            return false;
+        }
+        field
+            .split('_')
+            .any(|part| SENSITIVE_FIELDS.contains(&part.to_ascii_lowercase().as_str()))
+    }
+
+    pub fn redact(&self, fields: &mut HashMap<String, serde_json::Value>) {
+        for (name, value) in fields.iter_mut() {
+            if self.is_sensitive(name) {
+                *value = serde_json::json!(REDACTED);
+            }
+        }
+    }
+}
+
+/// The loggers records are dispatched to. Loggers can be added and removed
+/// after the global subscriber has been installed.
+#[derive(Debug, Default)]
+pub struct LogSinks {
+    redactor: RwLock<Redactor>,
+    loggers: RwLock<Vec<(u64, Arc<dyn Logger>)>>,
+    next_id: AtomicU64,
+}
+
+impl LogSinks {
+    pub fn new(redactor: Redactor) -> Self {
+        Self {
+            redactor: RwLock::new(redactor),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_redactor(&self, redactor: Redactor) {
+        *self.redactor.write().unwrap() = redactor;
+    }
+
+    pub fn add(&self, logger: Arc<dyn Logger>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.loggers.write().unwrap().push((id, logger));
+        id
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.loggers.write().unwrap().retain(|(i, _)| *i != id);
+    }
+
+    pub fn clear(&self) {
+        self.loggers.write().unwrap().clear();
+    }
+
+    fn loggers(&self) -> Vec<Arc<dyn Logger>> {
+        let loggers = self.loggers.read().unwrap();
+        loggers.iter().map(|(_, logger)| logger.clone()).collect()
+    }
+}
+
+/// Keeps a logger registered; dropping the handle, or calling `remove`,
+/// unregisters it.
+#[derive(uniffi::Object)]
+pub struct LoggerHandle {
+    id: u64,
+    sinks: Arc<LogSinks>,
+}
+
+impl LoggerHandle {
+    pub fn new(sinks: Arc<LogSinks>, logger: Arc<dyn Logger>) -> Arc<Self> {
+        let id = sinks.add(logger);
+        Arc::new(Self { id, sinks })
+    }
+}
+
+#[uniffi::export]
+impl LoggerHandle {
+    /// Unregisters the logger without waiting for the handle to be dropped.
+    pub fn remove(&self) {
+        self.sinks.remove(self.id);
+    }
+}
+
+impl Drop for LoggerHandle {
+    fn drop(&mut self) {
+        self.sinks.remove(self.id);
+    }
+}
+
+pub struct FFILogLayer {
+    sinks: Arc<LogSinks>,
+}
+
+impl FFILogLayer {
+    pub fn new(sinks: Arc<LogSinks>) -> Self {
+        Self { sinks }
+    }
+
+    fn record_fields(&self, record: impl FnOnce(&mut LogVisitor<'_>)) -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+        record(&mut LogVisitor(&mut fields));
+        self.sinks.redactor.read().unwrap().redact(&mut fields);
+        fields
+            .into_iter()
+            .map(|(name, value)| (name, field_to_string(value)))
+            .collect()
+    }
+}
+
+/// Redacted fields of a span, kept in its extensions until the span closes.
+struct SpanFields(HashMap<String, String>);
+
+impl<S> Layer<S> for FFILogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let fields = self.record_fields(|visitor| attrs.record(visitor));
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let fields = self.record_fields(|visitor| values.record(visitor));
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(SpanFields(existing)) => existing.extend(fields),
+            None => extensions.insert(SpanFields(fields)),
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // copied so a logger that logs itself doesn't deadlock the registry
+        let loggers = self.sinks.loggers();
+        if loggers.is_empty() {
+            return;
+        }
+
+        let mut fields = self.record_fields(|visitor| event.record(visitor));
+        let message = fields.remove("message").unwrap_or_default();
+        fields.remove("name");
+
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| LogSpan {
+                        name: span.name().to_string(),
+                        fields: span
+                            .extensions()
+                            .get::<SpanFields>()
+                            .map(|SpanFields(fields)| fields.clone())
+                            .unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        let record = LogRecord {
+            level: metadata.level().into(),
+            timestamp,
+            target: metadata.target().to_string(),
+            module: metadata.module_path().map(|m| m.to_string()),
+            message,
+            fields,
+            spans,
+        };
+        for logger in loggers {
+            logger.log(record.clone());
+        }
+    }
+}
+
+fn field_to_string(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        value => value.to_string(),
+    }
+}
+
+struct LogVisitor<'a>(&'a mut HashMap<String, serde_json::Value>);
+
+impl<'a> tracing::field::Visit for LogVisitor<'a> {
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_i128(&mut self, field: &tracing::field::Field, value: i128) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_u128(&mut self, field: &tracing::field::Field, value: u128) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().into(), serde_json::json!(value));
+    }
+
+    fn record_error(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &(dyn std::error::Error + 'static),
+    ) {
+        self.0.insert(
+            field.name().into(),
+            serde_json::json!(format!("error: {}", value)),
+        );
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(
+            field.name().into(),
+            serde_json::json!(format!("{:?}", value)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::{FFILogLayer, LogLevel, LogRecord, LogSinks, Logger, LoggerHandle, Redactor};
+
+    #[derive(Debug, Default)]
+    struct TestLogger(Mutex<Vec<LogRecord>>);
+
+    impl Logger for TestLogger {
+        fn log(&self, record: LogRecord) {
+            self.0.lock().unwrap().push(record);
+        }
+    }
+
+    #[test]
+    fn test_redaction() {
+        let logger = Arc::new(TestLogger::default());
+        let sinks = Arc::new(LogSinks::new(Redactor::new(["service_key".to_string()])));
+        sinks.add(logger.clone());
+        let subscriber = Registry::default().with(FFILogLayer::new(sinks));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                secret = "hoge",
+                account = "dameleon",
+                user_key = "fuga",
+                service_key = "github",
+                version = 3,
+                "adding token"
+            );
+        });
+
+        let logs = logger.0.lock().unwrap();
+        assert_eq!(logs.len(), 1);
+        let fields = &logs[0].fields;
+        assert_eq!(logs[0].message, "adding token");
+        assert_eq!(fields["secret"], "<redacted>");
+        assert_eq!(fields["account"], "<redacted>");
+        assert_eq!(fields["user_key"], "<redacted>");
+        assert_eq!(fields["service_key"], "github");
+        assert_eq!(fields["version"], "3");
+    }
+
+    #[test]
+    fn test_log_record() {
+        let logger = Arc::new(TestLogger::default());
+        let sinks = Arc::new(LogSinks::default());
+        sinks.add(logger.clone());
+        let subscriber = Registry::default().with(FFILogLayer::new(sinks));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::info_span!("migration", version = 1);
+            let _outer = outer.enter();
+            let inner = tracing::debug_span!("token", account = "dameleon", id = 42);
+            let _inner = inner.enter();
+            tracing::warn!(target: "auth2::test", "something happened");
+        });
+
+        let logs = logger.0.lock().unwrap();
+        assert_eq!(logs.len(), 1);
+        let record = &logs[0];
+        assert_eq!(record.level, LogLevel::Warn);
+        assert_eq!(record.target, "auth2::test");
+        assert_eq!(record.module.as_deref(), Some("auth2::logger::tests"));
+        assert_eq!(record.message, "something happened");
+        assert!(record.timestamp > 0);
+
+        let spans: Vec<_> = record.spans.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(spans, ["migration", "token"]);
+        assert_eq!(record.spans[0].fields["version"], "1");
+        assert_eq!(record.spans[1].fields["account"], "<redacted>");
+        assert_eq!(record.spans[1].fields["id"], "42");
+    }
+
+    #[test]
+    fn test_log_sinks() {
+        let first = Arc::new(TestLogger::default());
+        let second = Arc::new(TestLogger::default());
+        let sinks = Arc::new(LogSinks::default());
+        let subscriber = Registry::default().with(FFILogLayer::new(sinks.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let first_handle = LoggerHandle::new(sinks.clone(), first.clone());
+            tracing::info!("one");
+
+            let second_handle = LoggerHandle::new(sinks.clone(), second.clone());
+            tracing::info!("two");
+
+            drop(first_handle);
+            tracing::info!("three");
+
+            second_handle.remove();
+            tracing::info!("four");
+        });
+
+        let messages = |logger: &TestLogger| -> Vec<String> {
+            let logs = logger.0.lock().unwrap();
+            logs.iter().map(|record| record.message.clone()).collect()
+        };
+        assert_eq!(messages(&first), ["one", "two"]);
+        assert_eq!(messages(&second), ["two", "three"]);
+    }
+}
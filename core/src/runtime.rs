@@ -0,0 +1,141 @@
+use std::{future::Future, sync::OnceLock, thread};
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::error::Error;
+
+/// Where the bridge runs its work, see `configure_runtime`.
+#[derive(Debug, uniffi::Enum)]
+pub enum RuntimeConfig {
+    /// A multi-threaded runtime owned by the library, with one worker per
+    /// core unless `worker_threads` is given.
+    Global { worker_threads: Option<u32> },
+    /// Whatever tokio runtime the caller is running on. Only useful to Rust
+    /// applications that embed the bridge in their own runtime.
+    Ambient,
+}
+
+enum Rt {
+    Global(Runtime),
+    Ambient,
+}
+
+static RT: OnceLock<Rt> = OnceLock::new();
+
+/// Chooses the runtime used by `Auth2Bridge`. Must be called before the
+/// first bridge is created, otherwise a global runtime with default
+/// settings is already in use and this fails.
+#[uniffi::export]
+pub fn configure_runtime(config: RuntimeConfig) -> Result<(), Error> {
+    let mut config = Some(config);
+    RT.get_or_init(|| build(config.take().unwrap()));
+    match config {
+        None => Ok(()),
+        Some(_) => Err(Error::InternalError("runtime is already configured".into())),
+    }
+}
+
+fn build(config: RuntimeConfig) -> Rt {
+    match config {
+        RuntimeConfig::Global { worker_threads } => {
+            let mut builder = Builder::new_multi_thread();
+            if let Some(worker_threads) = worker_threads {
+                builder.worker_threads(worker_threads.max(1) as usize);
+            }
+            Rt::Global(
+                builder
+                    .enable_all()
+                    .build()
+                    .expect("failed to initialize tokio runtime"),
+            )
+        }
+        RuntimeConfig::Ambient => Rt::Ambient,
+    }
+}
+
+fn handle() -> Result<Handle, Error> {
+    let rt = RT.get_or_init(|| {
+        build(RuntimeConfig::Global {
+            worker_threads: None,
+        })
+    });
+    match rt {
+        Rt::Global(rt) => Ok(rt.handle().clone()),
+        Rt::Ambient => Handle::try_current()
+            .map_err(|_| Error::InternalError("called outside of a tokio runtime".into())),
+    }
+}
+
+/// Runs `future` to completion on the configured runtime. The bridge is
+/// called from foreign executors that know nothing about tokio, so its
+/// async methods hop over with this.
+pub(crate) async fn spawn<F>(future: F) -> Result<F::Output, Error>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Ok(handle()?.spawn(future).await?)
+}
+
+/// Blocks on `future` without panicking when the caller is itself inside a
+/// tokio runtime, by blocking on another thread in that case.
+pub(crate) fn block_on<F>(future: F) -> Result<F::Output, Error>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    let handle = handle()?;
+    if Handle::try_current().is_err() {
+        return Ok(handle.block_on(future));
+    }
+    thread::scope(|s| {
+        s.spawn(|| handle.block_on(future))
+            .join()
+            .map_err(|_| Error::InternalError("runtime thread panicked".into()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{configure_runtime, RuntimeConfig};
+    use crate::{bridge::Auth2Bridge, config::Config, key_store::MemoryKeyStore};
+
+    fn config() -> Config {
+        Config::new("sqlite::memory:", MemoryKeyStore::new("test"))
+    }
+
+    async fn add_token(bridge: &Auth2Bridge) -> Result<(), crate::Error> {
+        bridge
+            .add_token(
+                "dameleon".into(),
+                Some("GitHub".into()),
+                "JBSWY3DPEHPK3PXP".into(),
+                None,
+                None,
+                None,
+            )
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn test_bridge_inside_runtime() {
+        // would panic with a plain `block_on` on the global runtime
+        let bridge = Auth2Bridge::new(config()).unwrap();
+        bridge.db_run_migration().await.unwrap();
+        add_token(&bridge).await.unwrap();
+        assert_eq!(bridge.list_tokens(None).await.unwrap().len(), 1);
+
+        // the default runtime is in use by now
+        assert!(configure_runtime(RuntimeConfig::Ambient).is_err());
+
+        bridge.shutdown().await.unwrap();
+        assert!(add_token(&bridge).await.is_err());
+        assert!(bridge.list_tokens(None).await.is_err());
+
+        let bridge = Auth2Bridge::new_async(config()).await.unwrap();
+        bridge.db_run_migration().await.unwrap();
+        add_token(&bridge).await.unwrap();
+        assert_eq!(bridge.list_tokens(None).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,60 @@
+use std::{collections::HashMap, fmt};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    db::tokens::TokenData,
+    enc::{blind_index, decrypt_secret, encrypt_secret},
+    error::Error,
+};
+
+/// State held while the vault is unlocked: the data key, the blind index
+/// key and the decrypted metadata of every token, so listing tokens doesn't
+/// have to decrypt each row.
+pub struct Vault {
+    pub data_key: Zeroizing<Vec<u8>>,
+    pub index_key: Zeroizing<[u8; 32]>,
+    pub metadata: HashMap<u64, TokenMetadata>,
+}
+
+#[derive(Clone)]
+pub struct TokenMetadata {
+    pub account: String,
+    pub service: Option<String>,
+}
+
+impl fmt::Debug for TokenMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenMetadata")
+            .field("account", &"<redacted>")
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+/// Encrypts `account` and `service` of `data` in place and fills in their
+/// blind indexes.
+pub fn seal(user_key: &[u8], index_key: &[u8], data: &mut TokenData) -> anyhow::Result<()> {
+    data.account_index = Some(blind_index(index_key, &data.account));
+    data.service_index = data
+        .service
+        .as_deref()
+        .map(|service| blind_index(index_key, service));
+
+    data.account = encrypt_secret(user_key, &data.account)?;
+    data.service = data
+        .service
+        .as_deref()
+        .map(|service| encrypt_secret(user_key, service))
+        .transpose()?;
+    Ok(())
+}
+
+pub fn open(user_key: &[u8], account: &str, service: Option<&str>) -> Result<TokenMetadata, Error> {
+    Ok(TokenMetadata {
+        account: decrypt_secret(user_key, account)?.to_string(),
+        service: service
+            .map(|service| decrypt_secret(user_key, service).map(|s| s.to_string()))
+            .transpose()?,
+    })
+}
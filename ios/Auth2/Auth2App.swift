@@ -10,8 +10,11 @@ import os
 
 @main
 struct Auth2App: App {
+    // the logger is unregistered once its handle is released
+    private static var loggerHandle: LoggerHandle?
+
     init() {
-        initLogger(logger: DebugLogger())
+        Auth2App.loggerHandle = initLogger(logger: DebugLogger())
     }
     
     var body: some Scene {
@@ -28,17 +31,27 @@ class DebugLogger: Logger {
         self.logger = os.Logger(subsystem: "dev.typester.auth2", category: "core")
     }
 
-    func log(msg: String) {
-        logger.debug("\(msg)")
+    func log(record: LogRecord) {
+        let spans = record.spans.map { $0.name }.joined(separator: ":")
+        let msg = "[\(record.target)\(spans.isEmpty ? "" : " \(spans)")] \(record.message) \(record.fields)"
+        switch record.level {
+        case .error:
+            logger.error("\(msg)")
+        case .warn:
+            logger.warning("\(msg)")
+        case .info:
+            logger.info("\(msg)")
+        case .debug:
+            logger.debug("\(msg)")
+        case .trace:
+            logger.trace("\(msg)")
+        }
     }
 }
 
 extension Auth2Bridge {
     private static var sharedInstance: Auth2Bridge = {
         let databaseUrl = FileManager.default.urls(for: .documentDirectory, in: .userDomainMask)[0].appendingPathComponent("database.db")
-        if !FileManager.default.fileExists(atPath: databaseUrl.path()) {
-            FileManager.default.createFile(atPath: databaseUrl.path(), contents: nil)
-        }
 
         let config = Config(databaseUrl: "sqlite://\(databaseUrl.path())", keyStore: iOSKeyStore())
         let bridge = try! Auth2Bridge(config: config)
@@ -13,18 +13,20 @@ struct ContentView: View {
 
     var body: some View {
         Group {
-            if !isMigrationComplete {
-                DataMigrationScreen(isMigrationComplete: $isMigrationComplete)
-            } else if !isEncryptionKeyAbailable {
+            // the key comes first: with SQLCipher the database can't be
+            // opened, let alone migrated, without it
+            if !isEncryptionKeyAbailable {
                 CreateEncryptionKeyScreen {
                     isEncryptionKeyAbailable = true
                 }
+            } else if !isMigrationComplete {
+                DataMigrationScreen(isMigrationComplete: $isMigrationComplete)
             } else {
                 TokenListScreen()
             }
         }
         .onAppear {
-            if let _ = iOSKeyStore().get() {
+            if let _ = try? iOSKeyStore().get() {
                 isEncryptionKeyAbailable = true
             }
         }
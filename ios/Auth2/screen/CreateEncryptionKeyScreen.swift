@@ -14,27 +14,48 @@ enum KeychainKeys {
 }
 
 class iOSKeyStore: KeyStore {
-    func get() -> String? {
-        let keychainQuery: [String: Any] = [
+    private var baseQuery: [String: Any] {
+        [
             kSecClass as String: kSecClassGenericPassword,
             kSecAttrService as String: KeychainKeys.serviceName,
             kSecAttrAccount as String: KeychainKeys.encryptionKey,
-            kSecReturnData as String: true,
-            kSecMatchLimit as String: kSecMatchLimitOne
         ]
+    }
+
+    func get() throws -> Data? {
+        var keychainQuery = baseQuery
+        keychainQuery[kSecReturnData as String] = true
+        keychainQuery[kSecMatchLimit as String] = kSecMatchLimitOne
+
         var item: AnyObject?
         let status = SecItemCopyMatching(keychainQuery as CFDictionary, &item)
-        
-        if status != errSecSuccess {
-            print("failed to get encryptionKey: status=\(status)")
+        switch status {
+        case errSecSuccess:
+            return item as? Data
+        case errSecItemNotFound:
             return nil
+        case errSecUserCanceled:
+            throw KeyStoreError.Cancelled
+        default:
+            throw KeyStoreError.Failed("failed to get encryptionKey: status=\(status)")
         }
-        
-        if let data = item as? Data, let encryptionKey = String(data: data, encoding: .utf8) {
-            return encryptionKey
-        } else {
-            print("failed to decode encryption key")
-            return nil
+    }
+
+    func set(key: Data) throws {
+        try delete()
+
+        var keychainQuery = baseQuery
+        keychainQuery[kSecValueData as String] = key
+        let status = SecItemAdd(keychainQuery as CFDictionary, nil)
+        if status != errSecSuccess {
+            throw KeyStoreError.Failed("failed to save encryptionKey: status=\(status)")
+        }
+    }
+
+    func delete() throws {
+        let status = SecItemDelete(baseQuery as CFDictionary)
+        if status != errSecSuccess && status != errSecItemNotFound {
+            throw KeyStoreError.Failed("failed to delete encryptionKey: status=\(status)")
         }
     }
 }
@@ -59,21 +80,13 @@ struct CreateEncryptionKeyScreen: View {
     }
     
     private func saveKey() {
-        let keychainQuery: [String: Any] = [
-            kSecClass as String: kSecClassGenericPassword,
-            kSecAttrService as String: KeychainKeys.serviceName,
-            kSecAttrAccount as String: KeychainKeys.encryptionKey,
-            kSecValueData as String: encryptionKey.data(using: .utf8)!
-        ]
-        
-        let status = SecItemAdd(keychainQuery as CFDictionary, nil)
-        
-        if status != errSecSuccess {
-            print("failed to save encryptionKey: status=\(status)")
+        do {
+            try iOSKeyStore().set(key: encryptionKey.data(using: .utf8)!)
+        } catch {
+            print("\(error)")
             return
         }
-        
+
         onSave()
     }
 }
-
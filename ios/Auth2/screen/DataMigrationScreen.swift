@@ -11,6 +11,8 @@ struct DataMigrationScreen: View {
     @Binding var isMigrationComplete: Bool
     @State private var migrating: Bool = false
     @State private var showAlert: Bool = false
+    // issued when the alert opens, used only by its reset button
+    @State private var resetToken: String?
     
     var body: some View {
         VStack {
@@ -28,8 +30,11 @@ struct DataMigrationScreen: View {
                 message: Text("Failed to upgrade database. Need to reset."),
                 dismissButton: .destructive(Text("Reset")) {
                     showAlert = false
-                    Task {
-                        await reset()
+                    if let confirmation = resetToken {
+                        resetToken = nil
+                        Task {
+                            await reset(confirmation: confirmation)
+                        }
                     }
                 }
             )
@@ -49,13 +54,14 @@ struct DataMigrationScreen: View {
             isMigrationComplete = true
         } catch {
             print("migration error: \(error)")
+            resetToken = Auth2Bridge.shared().dbResetToken()
             showAlert = true
         }
     }
     
-    private func reset() async {
+    private func reset(confirmation: String) async {
         do {
-            try await Auth2Bridge.shared().dbReset()
+            try await Auth2Bridge.shared().dbReset(confirmation: confirmation)
             await migrate()
         } catch {
             print("reset failed: \(error)")
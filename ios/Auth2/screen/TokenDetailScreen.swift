@@ -141,6 +141,6 @@ struct TokenDetailScreen: View {
 #Preview {
     TokenDetailScreen(
         id: 123,
-        token: TokenDetail(id: 123, account: "dameleon", service: "Foo", algorithm: .sha1, digits: 6, period: 30)
+        token: TokenDetail(id: 123, account: "dameleon", service: "Foo", algorithm: .sha1, digits: 6, period: 30, lastUsedAt: nil, useCount: 0, pinned: false)
     )
 }
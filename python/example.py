@@ -0,0 +1,69 @@
+"""Lists the current codes of a database through the Python bindings.
+
+Run core/build-python-lib.sh first, then:
+
+    AUTH2_PASSPHRASE=... python example.py path/to/auth2.db
+"""
+
+import asyncio
+import logging
+import os
+import sys
+
+import auth2
+
+LEVELS = {
+    auth2.LogLevel.ERROR: logging.ERROR,
+    auth2.LogLevel.WARN: logging.WARNING,
+    auth2.LogLevel.INFO: logging.INFO,
+    auth2.LogLevel.DEBUG: logging.DEBUG,
+    auth2.LogLevel.TRACE: logging.DEBUG,
+}
+
+
+class EnvKeyStore(auth2.KeyStore):
+    """Hands the core the passphrase from `AUTH2_PASSPHRASE`."""
+
+    def get(self):
+        passphrase = os.environ.get("AUTH2_PASSPHRASE")
+        return passphrase.encode() if passphrase is not None else None
+
+    def set(self, key):
+        raise auth2.KeyStoreError.Unsupported()
+
+    def delete(self):
+        raise auth2.KeyStoreError.Unsupported()
+
+
+class PythonLogger(auth2.Logger):
+    """Forwards core log records to the `logging` module."""
+
+    def __init__(self):
+        self.logger = logging.getLogger("auth2.core")
+
+    def log(self, record):
+        spans = ":".join(span.name for span in record.spans)
+        target = f"{record.target} {spans}" if spans else record.target
+        self.logger.log(
+            LEVELS[record.level], "[%s] %s %s", target, record.message, record.fields
+        )
+
+
+async def main(database):
+    bridge = auth2.Auth2Bridge(
+        auth2.Config(database_url=f"sqlite://{database}", key_store=EnvKeyStore())
+    )
+    if await bridge.db_is_migration_available():
+        await bridge.db_run_migration()
+
+    for token in await bridge.list_tokens():
+        result = await bridge.generate_current(token.id)
+        name = f"{token.service}:{token.account}" if token.service else token.account
+        print(f"{result.current} {result.expires:>2}s {name}")
+
+
+if __name__ == "__main__":
+    logging.basicConfig(level=logging.INFO)
+    # keep the handle, the logger is removed once it is garbage collected
+    handle = auth2.init_logger(PythonLogger(), max_level=auth2.LogLevel.INFO)
+    asyncio.run(main(sys.argv[1]))
@@ -0,0 +1,54 @@
+import asyncio
+import os
+import sys
+
+import pytest
+
+# the generated module and library live in the parent directory
+sys.path.insert(0, os.path.dirname(os.path.dirname(os.path.abspath(__file__))))
+
+import auth2  # noqa: E402
+
+
+class StaticKeyStore(auth2.KeyStore):
+    def __init__(self, key):
+        self.key = key
+
+    def get(self):
+        return self.key
+
+    def set(self, key):
+        self.key = key
+
+    def delete(self):
+        self.key = None
+
+
+class RecordingLogger(auth2.Logger):
+    def __init__(self):
+        self.records = []
+
+    def log(self, record):
+        self.records.append(record)
+
+
+@pytest.fixture
+def database_url(tmp_path):
+    return f"sqlite://{tmp_path / 'auth2.db'}"
+
+
+@pytest.fixture
+def bridge(database_url):
+    bridge = auth2.Auth2Bridge(
+        auth2.Config(database_url=database_url, key_store=StaticKeyStore(b"test"))
+    )
+    asyncio.run(bridge.db_run_migration())
+    return bridge
+
+
+@pytest.fixture
+def logger():
+    logger = RecordingLogger()
+    handle = auth2.init_logger(logger, max_level=auth2.LogLevel.DEBUG)
+    yield logger
+    handle.remove()
@@ -0,0 +1,125 @@
+import asyncio
+
+import pytest
+
+import auth2
+from conftest import StaticKeyStore
+
+SECRET = "JBSWY3DPEHPK3PXP"
+
+
+def test_add_list_generate(bridge):
+    async def scenario():
+        detail = await bridge.add_token("alice", "GitHub", SECRET, None, None, None)
+        assert detail.account == "alice"
+        assert detail.service == "GitHub"
+        assert detail.algorithm == auth2.TokenAlg.SHA1
+        assert detail.digits == 6
+        assert detail.period == 30
+
+        await bridge.add_token("bob", None, SECRET, auth2.TokenAlg.SHA256, 8, 60)
+
+        tokens = await bridge.list_tokens()
+        assert sorted(t.account for t in tokens) == ["alice", "bob"]
+
+        bob = next(t for t in tokens if t.account == "bob")
+        result = await bridge.generate_current(bob.id)
+        assert len(result.current) == 8
+        assert result.current.isdigit()
+        assert 0 < result.expires <= 60
+
+        detail = await bridge.token_detail(bob.id)
+        assert detail.use_count == 1
+
+    asyncio.run(scenario())
+
+
+def test_add_from_url(bridge):
+    async def scenario():
+        detail = await bridge.add_token_from_url(
+            f"otpauth://totp/GitLab:carol?secret={SECRET}&issuer=GitLab&digits=7"
+        )
+        assert detail.account == "carol"
+        assert detail.service == "GitLab"
+
+        result = await bridge.generate_current(detail.id)
+        assert len(result.current) == 7
+
+        await bridge.remove_token(detail.id)
+        assert await bridge.list_tokens() == []
+
+    asyncio.run(scenario())
+
+
+def test_wrong_passphrase(bridge, database_url):
+    asyncio.run(bridge.add_token("alice", "GitHub", SECRET, None, None, None))
+
+    other = auth2.Auth2Bridge(
+        auth2.Config(database_url=database_url, key_store=StaticKeyStore(b"wrong"))
+    )
+    with pytest.raises(auth2.Error.DecryptError):
+        asyncio.run(other.list_tokens())
+
+
+def test_missing_key(bridge, database_url):
+    other = auth2.Auth2Bridge(
+        auth2.Config(database_url=database_url, key_store=StaticKeyStore(None))
+    )
+    with pytest.raises(auth2.Error.InternalError):
+        asyncio.run(other.add_token("alice", None, SECRET, None, None, None))
+
+
+def test_logger(bridge, logger):
+    asyncio.run(bridge.add_token("alice", "GitHub", SECRET, None, None, None))
+
+    assert logger.records
+    for record in logger.records:
+        assert isinstance(record.level, auth2.LogLevel)
+        assert SECRET not in record.message
+        assert SECRET not in record.fields.values()
+
+
+def test_async_constructor_and_shutdown(database_url):
+    async def scenario():
+        bridge = await auth2.Auth2Bridge.new_async(
+            auth2.Config(database_url=database_url, key_store=StaticKeyStore(b"test"))
+        )
+        await bridge.db_run_migration()
+        await bridge.add_token("alice", None, SECRET, None, None, None)
+        assert len(await bridge.list_tokens()) == 1
+
+        await bridge.shutdown()
+        with pytest.raises(auth2.Error):
+            await bridge.list_tokens()
+
+    asyncio.run(scenario())
+
+
+def test_key_store_error(bridge, database_url):
+    class CancellingKeyStore(StaticKeyStore):
+        def get(self):
+            raise auth2.KeyStoreError.Cancelled()
+
+    other = auth2.Auth2Bridge(
+        auth2.Config(database_url=database_url, key_store=CancellingKeyStore(None))
+    )
+    with pytest.raises(auth2.Error.KeyStoreError):
+        asyncio.run(other.add_token("alice", None, SECRET, None, None, None))
+
+
+def test_key_slots(bridge, database_url):
+    token = asyncio.run(bridge.add_token("alice", "GitHub", SECRET, None, None, None))
+    new = asyncio.run(bridge.add_recovery_key("paper"))
+    slots = asyncio.run(bridge.list_key_slots())
+    assert [slot.kind for slot in slots] == [
+        auth2.KeySlotKind.PASSPHRASE,
+        auth2.KeySlotKind.RECOVERY_KEY,
+    ]
+    assert slots[1].label == "paper"
+
+    # the user key is gone, the recovery key still opens the vault
+    other = auth2.Auth2Bridge(
+        auth2.Config(database_url=database_url, key_store=StaticKeyStore(None))
+    )
+    asyncio.run(other.unlock_with_recovery_key(new.recovery_key))
+    assert asyncio.run(other.token_detail(token.id)).account == "alice"